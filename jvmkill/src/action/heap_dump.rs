@@ -14,79 +14,425 @@
  * limitations under the License.
  */
 
-use std::{env, fs};
-use std::path::PathBuf;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::ffi::{CString, OsString};
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 
-use time::OffsetDateTime;
+use flate2::write::GzEncoder;
+use serde_json::json;
 
 use crate::action;
 use crate::action::Action;
-use crate::bindings::jint;
+use crate::bindings::{jint, JNI_TRUE, jvmtiCapabilities};
+use crate::context::{Compression, HeapDumpWriter, Parameters};
+use crate::heap::Hprof;
 use crate::jmx::ManagementFactory;
 use crate::jni::JNI;
+use crate::jvmti::JVMTI;
+use crate::path_template::PathTemplate;
+use crate::report::Report;
 
-pub struct HeapDump<'h, J: JNI> {
-    factory: &'h ManagementFactory<'h, J>,
-    path: PathBuf,
+// The first Java feature release whose GC.heap_dump diagnostic command supports -gz
+const NATIVE_GZIP_VERSION: u32 = 15;
+
+// The first Java feature release whose GC.heap_dump diagnostic command supports -parallel
+const PARALLEL_VERSION: u32 = 18;
+
+pub struct HeapDump<'h, J: JVMTI, N: JNI> {
+    compression: Option<Compression>,
+    factory: &'h ManagementFactory<'h, N>,
+    fallback_directory: Option<PathTemplate>,
+    heap_walked: &'h Cell<bool>,
+    jni: &'h N,
+    jvmti: &'h J,
+    live_only: bool,
+    max_count: Option<usize>,
+    max_total_size: Option<u64>,
+    parallel: Option<usize>,
+    path: PathTemplate,
+    report: &'h Report,
+    writer: HeapDumpWriter,
 }
 
-impl<'h, J: JNI> HeapDump<'h, J> {
-    pub fn new(factory: &'h ManagementFactory<J>, path: &PathBuf) -> Self {
-        let mut p = env::current_dir()
-            .expect("current directory not found");
+impl<'h, J: JVMTI, N: JNI> HeapDump<'h, J, N> {
+    pub fn new(jvmti: &'h J, jni: &'h N, factory: &'h ManagementFactory<N>, parameters: &Parameters, heap_walked: &'h Cell<bool>, report: &'h Report) -> Self {
+        let path = parameters.heap_dump_path.as_ref().expect("heapDumpPath must be set");
+
+        return Self {
+            compression: parameters.heap_dump_compression,
+            factory,
+            fallback_directory: parameters.heap_dump_fallback_directory.as_ref().map(PathTemplate::new),
+            heap_walked,
+            jni,
+            jvmti,
+            live_only: parameters.heap_dump_live_only,
+            max_count: parameters.heap_dump_max_count,
+            max_total_size: parameters.heap_dump_max_total_size,
+            parallel: parameters.heap_dump_parallel,
+            path: PathTemplate::new(path),
+            report,
+            writer: parameters.heap_dump_writer,
+        };
+    }
+
+    // The GC.heap_dump diagnostic command is used only where the HotSpot Diagnostic MXBean lacks an option: gzip
+    // compression or parallel dumping on the Java versions that support them.
+    fn dump_heap(&self, path: &Path) -> Result<PathBuf, String> {
+        let version = if self.compression == Some(Compression::Gzip) || self.parallel.is_some() { self.runtime_version() } else { 0 };
+
+        let gzip = self.compression == Some(Compression::Gzip) && version >= NATIVE_GZIP_VERSION;
+        let parallel = self.parallel.filter(|_| version >= PARALLEL_VERSION);
+
+        let p = if !gzip && parallel.is_none() {
+            self.factory.get_hotspot_diagnostic_mxbean().dump_heap(path.to_str().unwrap(), self.live_only);
+            path.to_path_buf()
+        } else {
+            let p = if gzip { compressed_path(path, Compression::Gzip) } else { path.to_path_buf() };
+            let arguments = gc_heap_dump_arguments(&p, self.live_only, gzip, parallel);
+
+            check_gc_heap_dump(self.factory.get_diagnostic_command_mbean().gc_heap_dump(&arguments.iter().map(String::as_str).collect::<Vec<&str>>()))?;
+            p
+        };
+
+        if !p.is_file() {
+            return Err(format!("cannot create heap dump since {:?} was not created", p));
+        }
+
+        return match self.compression {
+            Some(c) if !gzip => compress(&p, c).map_err(|e| format!("cannot compress heap dump {:?}: {}", p, e)),
+            _ => Ok(p),
+        };
+    }
+
+    // Checks that the directory of the dump has room for the used heap, an approximation of the size of an uncompressed
+    // dump, and otherwise moves the dump to the fallback directory if that has room.
+    fn preflight(&self, path: PathBuf) -> Result<PathBuf, String> {
+        let required = self.factory.get_memory_mxbean().get_heap_memory_usage().get_used().max(0) as u64;
+
+        let directory = match path.parent() {
+            Some(d) => d,
+            None => return Err(format!("cannot create heap dump since {:?} has no parent directory", path)),
+        };
+
+        let available = match free_space(directory) {
+            Some(a) if a < required => a,
+            _ => return Ok(path),
+        };
+
+        let reason = format!("{:?} has {} bytes free but the heap uses {} bytes", directory, available, required);
+
+        let fallback = match &self.fallback_directory {
+            Some(f) => f.resolve(),
+            None => return Err(format!("cannot create heap dump since {}", reason)),
+        };
+
+        if let Err(e) = fs::create_dir_all(&fallback) {
+            return Err(format!("cannot create heap dump since {} and fallback directory {:?} cannot be created: {}", reason, fallback, e));
+        }
+
+        match free_space(&fallback) {
+            Some(a) if a < required => return Err(format!("cannot create heap dump since {} and fallback {:?} has {} bytes free", reason, fallback, a)),
+            _ => {}
+        }
 
-        p.push(path);
+        let name = match path.file_name() {
+            Some(n) => n,
+            None => return Err(format!("cannot create heap dump since {:?} has no file name", path)),
+        };
 
-        return Self { factory, path: p };
+        self.report.println(&format!("Using fallback directory {:?} since {}", fallback, reason));
+        return Ok(fallback.join(name));
     }
 
-    fn create_parent(&self, path: &PathBuf) {
-        let p = path.parent()
-            .expect("unable to find heap dump parent directory");
+    // Deletes the oldest earlier dumps matching the path template, newest first, once keeping another would exceed
+    // either limit. The dump just written always counts towards the limits and is never deleted.
+    fn rotate(&self, current: &Path) -> Vec<PathBuf> {
+        let directory = match current.parent() {
+            Some(d) => d,
+            None => return Vec::new(),
+        };
+
+        let entries = match fs::read_dir(directory) {
+            Ok(e) => e,
+            Err(e) => {
+                self.report.println(&format!("Skipping heap dump rotation since {:?} cannot be read: {}", directory, e));
+                return Vec::new();
+            }
+        };
+
+        let mut dumps: Vec<(PathBuf, fs::Metadata)> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p != current && self.path.matches(p))
+            .filter_map(|p| fs::metadata(&p).ok().filter(|m| m.is_file()).map(|m| (p, m)))
+            .collect();
+
+        dumps.sort_by_key(|(_, m)| Reverse(m.modified().ok()));
 
-        fs::create_dir_all(p)
-            .expect("unable to create heap dump parent directory");
+        let mut count = 1;
+        let mut total_size = fs::metadata(current).map(|m| m.len()).unwrap_or(0);
+        let mut full = false;
+        let mut deleted = Vec::new();
+
+        for (p, m) in dumps {
+            full = full
+                || self.max_count.is_some_and(|c| count + 1 > c)
+                || self.max_total_size.is_some_and(|s| total_size + m.len() > s);
+
+            if !full {
+                count += 1;
+                total_size += m.len();
+                continue;
+            }
+
+            match fs::remove_file(&p) {
+                Ok(_) => deleted.push(p),
+                Err(e) => self.report.println(&format!("Unable to delete heap dump {:?}: {}", p, e)),
+            }
+        }
+
+        return deleted;
+    }
+
+    fn runtime_version(&self) -> u32 {
+        return feature_version(&self.factory.get_runtime_mxbean().get_spec_version());
     }
 
-    fn resolve_path(&self) -> PathBuf {
-        return PathBuf::from(OffsetDateTime::now().format(self.path.to_str().unwrap()));
+    // Streams the dump written by the agent through the compressor, so no uncompressed copy is written first
+    fn write_hprof(&self, path: &Path) -> Result<PathBuf, String> {
+        let mut c = jvmtiCapabilities::default();
+        c.set_can_tag_objects(JNI_TRUE);
+        self.jvmti.add_capabilities(c);
+
+        let p = self.compression.map_or(path.to_path_buf(), |c| compressed_path(path, c));
+
+        let f = File::create(&p)
+            .map_err(|e| format!("cannot create heap dump {:?}: {}", p, e))?;
+
+        // The walk leaves object ids in the tags, which a later heap histogram has to reset
+        let h = Hprof::new(self.jvmti, self.jni);
+        self.heap_walked.set(true);
+
+        let r = match self.compression {
+            None => h.write(f),
+            Some(Compression::Gzip) => {
+                let mut e = GzEncoder::new(f, flate2::Compression::default());
+                h.write(&mut e).and_then(|_| e.finish()).map(|_| ())
+            }
+            Some(Compression::Zstd) => zstd::Encoder::new(f, 0)
+                .and_then(|mut e| h.write(&mut e).and_then(|_| e.finish()))
+                .map(|_| ()),
+        };
+
+        return match r {
+            Ok(_) => Ok(p),
+            Err(e) => Err(format!("cannot write heap dump {:?}: {}", p, e)),
+        };
     }
 }
 
-impl<'h, J: JNI> Action for HeapDump<'h, J> {
+impl<'h, J: JVMTI, N: JNI> Action for HeapDump<'h, J, N> {
     fn execute(&self, flags: jint) {
-        if action::is_threads_exhausted(flags) {
-            eprintln!("cannot create heap dump since the JVM is unable to create a thread");
+        let threads_exhausted = action::is_threads_exhausted(flags);
+
+        // The agent writer needs neither a new thread nor the HotSpot Diagnostic MXBean
+        let agent = match self.writer {
+            HeapDumpWriter::Agent => true,
+            HeapDumpWriter::Auto => threads_exhausted || !self.factory.has_hotspot_diagnostic_mxbean(),
+            HeapDumpWriter::MXBean => false,
+        };
+
+        if threads_exhausted && !agent {
+            self.report.skip("heapDump", "cannot create heap dump since the JVM is unable to create a thread");
+            return;
+        }
+
+        self.report.println("\n>>> Heap Dump");
+
+        let p = self.path.resolve();
+        if let Err(e) = PathTemplate::create_parent(&p) {
+            self.report.skip("heapDump", &format!("cannot create heap dump since {:?} cannot be created: {}", p.parent().unwrap_or(&p), e));
             return;
         }
 
-        println!("\n>>> Heap Dump");
+        let p = match self.preflight(p) {
+            Ok(p) => p,
+            Err(reason) => {
+                self.report.skip("heapDump", &reason);
+                return;
+            }
+        };
 
-        let p = self.resolve_path();
-        self.create_parent(&p);
+        let p = match if agent { self.write_hprof(&p) } else { self.dump_heap(&p) } {
+            Ok(p) => p,
+            Err(reason) => {
+                self.report.skip("heapDump", &reason);
+                return;
+            }
+        };
+
+        self.report.println(&format!("Heap dump written to {:?}", p));
+
+        if self.max_count.is_none() && self.max_total_size.is_none() {
+            self.report.set("heapDump", json!({ "path": p }));
+            return;
+        }
+
+        let deleted = self.rotate(&p);
+        for d in &deleted {
+            self.report.println(&format!("Old heap dump deleted {:?}", d));
+        }
 
-        self.factory.get_hotspot_diagnostic_mxbean().dump_heap(p.to_str().unwrap());
-        println!("Heap dump written to {:?}", p);
+        self.report.set("heapDump", json!({ "deleted": deleted, "path": p }));
     }
 }
 
+// GC.heap_dump reports a failure, such as an existing file, only in its output
+fn check_gc_heap_dump(output: Option<String>) -> Result<(), String> {
+    return match output {
+        Some(o) if o.contains("Heap dump file created") => Ok(()),
+        Some(o) => Err(format!("cannot create heap dump since GC.heap_dump failed: {}", o.trim())),
+        None => Err(String::from("cannot create heap dump since GC.heap_dump returned no output")),
+    };
+}
+
+fn compress(path: &Path, compression: Compression) -> io::Result<PathBuf> {
+    let target = compressed_path(path, compression);
+
+    let mut input = File::open(path)?;
+    let output = File::create(&target)?;
+
+    match compression {
+        Compression::Gzip => {
+            let mut e = GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut e)?;
+            e.finish()?;
+        }
+        Compression::Zstd => zstd::stream::copy_encode(input, output, 0)?,
+    }
+
+    fs::remove_file(path)?;
+    return Ok(target);
+}
+
+fn compressed_path(path: &Path, compression: Compression) -> PathBuf {
+    let mut s = OsString::from(path.as_os_str());
+
+    s.push(match compression {
+        Compression::Gzip => ".gz",
+        Compression::Zstd => ".zst",
+    });
+
+    return PathBuf::from(s);
+}
+
+fn feature_version(spec_version: &str) -> u32 {
+    return spec_version.trim_start_matches("1.")
+        .split('.')
+        .next()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+}
+
+fn free_space(directory: &Path) -> Option<u64> {
+    let d = CString::new(directory.as_os_str().as_bytes()).ok()?;
+    let mut s: libc::statvfs = unsafe { mem::zeroed() };
+
+    if unsafe { libc::statvfs(d.as_ptr(), &mut s) } != 0 {
+        return None;
+    }
+
+    return Some(s.f_bavail as u64 * s.f_frsize as u64);
+}
+
+fn gc_heap_dump_arguments(path: &Path, live_only: bool, gzip: bool, parallel: Option<usize>) -> Vec<String> {
+    let mut arguments = Vec::new();
+
+    if !live_only {
+        arguments.push(String::from("-all"));
+    }
+
+    if gzip {
+        arguments.push(String::from("-gz=1"));
+    }
+
+    if let Some(p) = parallel {
+        arguments.push(format!("-parallel={}", p));
+    }
+
+    arguments.push(path.to_string_lossy().into_owned());
+    return arguments;
+}
+
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+    use std::fs;
+    use std::io::Read;
     use std::path::PathBuf;
     use std::ptr;
+    use std::time::{Duration, SystemTime};
 
+    use flate2::read::GzDecoder;
     use mockall::Sequence;
+    use serde_json::json;
 
     use crate::action::Action;
-    use crate::action::heap_dump::HeapDump;
+    use crate::action::heap_dump::{check_gc_heap_dump, compress, feature_version, gc_heap_dump_arguments, HeapDump};
     use crate::bindings::{jclass, jint, jmethodID, JNI_TRUE, jobject, jstring, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, JVMTI_RESOURCE_EXHAUSTED_THREADS};
+    use crate::context::{Compression, HeapDumpWriter, Parameters};
     use crate::jmx::ManagementFactory;
     use crate::jni::MockJNI;
+    use crate::jvmti::{ArrayPointerLoadedClassesIterator, MockJVMTI};
+    use crate::report::{Format, Report};
+
+    #[test]
+    fn check_gc_heap_dump_output() {
+        assert_eq!(check_gc_heap_dump(Some(String::from("Dumping heap to /test/heap.hprof ...\nHeap dump file created [42 bytes in 0.001 secs]\n"))), Ok(()));
+        assert_eq!(check_gc_heap_dump(Some(String::from("Unable to create /test/heap.hprof: File exists\n"))), Err(String::from("cannot create heap dump since GC.heap_dump failed: Unable to create /test/heap.hprof: File exists")));
+        assert_eq!(check_gc_heap_dump(None), Err(String::from("cannot create heap dump since GC.heap_dump returned no output")));
+    }
+
+    #[test]
+    fn compress_gzip() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("heap.hprof");
+        fs::write(&p, "test-heap-dump").unwrap();
+
+        let c = compress(&p, Compression::Gzip).unwrap();
+
+        assert_eq!(c, t.path().join("heap.hprof.gz"));
+        assert!(!p.exists());
+
+        let mut s = String::new();
+        GzDecoder::new(fs::File::open(&c).unwrap()).read_to_string(&mut s).unwrap();
+        assert_eq!(s, "test-heap-dump");
+    }
+
+    #[test]
+    fn compress_zstd() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("heap.hprof");
+        fs::write(&p, "test-heap-dump").unwrap();
+
+        let c = compress(&p, Compression::Zstd).unwrap();
+
+        assert_eq!(c, t.path().join("heap.hprof.zst"));
+        assert!(!p.exists());
+        assert_eq!(zstd::stream::decode_all(fs::File::open(&c).unwrap()).unwrap(), b"test-heap-dump");
+    }
 
     #[test]
     fn execute() {
-        let t = PathBuf::from(tempdir::TempDir::new("jvmkill").unwrap().path());
-        let u = t.clone();
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("heap.hprof");
+        let u = p.clone();
+        let w = p.clone();
 
         let mut jni = MockJNI::new();
         let mut seq = Sequence::new();
@@ -99,6 +445,240 @@ mod tests {
             .in_sequence(&mut seq)
             .return_once_st(move |_| Option::Some(c_management_factory));
 
+        expect_heap_used(&mut jni, &mut seq, c_management_factory, 42);
+
+        let m_get_platform_mxbean = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_management_factory)
+                    && a_method == "getPlatformMXBean"
+                    && a_signature == "(Ljava/lang/Class;)Ljava/lang/management/PlatformManagedObject;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_platform_mxbean));
+
+        let c_hot_spot_diagnostic_mxbean = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "com/sun/management/HotSpotDiagnosticMXBean")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_hot_spot_diagnostic_mxbean));
+
+        let i_hot_spot_diagnostic_mxbean = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method_a()
+            .withf_st(move |&a_class, &a_method, a_args| {
+                ptr::eq(c_management_factory, a_class)
+                    && ptr::eq(a_method, m_get_platform_mxbean)
+                    && ptr::eq(unsafe { a_args[0].l }, c_hot_spot_diagnostic_mxbean)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(i_hot_spot_diagnostic_mxbean));
+
+        let m_dump_heap = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_hot_spot_diagnostic_mxbean)
+                    && a_method == "dumpHeap"
+                    && a_signature == "(Ljava/lang/String;Z)V"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_dump_heap));
+
+        let s_p = jni_type!(jstring);
+        jni
+            .expect_new_string_utf()
+            .withf_st(move |a_s| a_s == u.to_str().unwrap())
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| s_p);
+
+        let e = jni_type!(jobject);
+        jni
+            .expect_call_object_method_a()
+            .withf_st(move |&a_instance, &a_method, a_args| {
+                ptr::eq(a_instance, i_hot_spot_diagnostic_mxbean)
+                    && ptr::eq(a_method, m_dump_heap)
+                    && ptr::eq(unsafe { a_args[0].l }, s_p)
+                    && unsafe { a_args[1].z } == JNI_TRUE as u8
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| {
+                fs::write(&w, "test-heap-dump").unwrap();
+                Option::Some(e)
+            });
+
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_path: Some(p.clone()), ..Default::default() }, &Cell::new(false), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert_eq!(report.document()["heapDump"], json!({ "path": p }));
+    }
+
+    #[test]
+    fn execute_agent() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+
+        let mut jni = MockJNI::new();
+        let mut jvmti = MockJVMTI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        expect_heap_used(&mut jni, &mut seq, c_management_factory, 42);
+
+        jvmti
+            .expect_add_capabilities()
+            .withf_st(|&a_capabilities| a_capabilities.can_tag_objects() == JNI_TRUE)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let loaded_classes = ArrayPointerLoadedClassesIterator { count: 0, classes: jni_type!(jclass) as *mut jclass };
+        jvmti
+            .expect_get_loaded_classes()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move || loaded_classes);
+
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_compression: Some(Compression::Gzip), heap_dump_path: Some(t.path().join("heap.hprof")), heap_dump_writer: HeapDumpWriter::Agent, ..Default::default() }, &Cell::new(false), &report)
+            .execute(JVMTI_RESOURCE_EXHAUSTED_THREADS as jint);
+
+        assert!(!t.path().join("heap.hprof").exists());
+        assert_eq!(report.document()["heapDump"], json!({ "path": t.path().join("heap.hprof.gz") }));
+
+        let mut b = Vec::new();
+        GzDecoder::new(fs::File::open(t.path().join("heap.hprof.gz")).unwrap()).read_to_end(&mut b).unwrap();
+        assert!(b.starts_with(b"JAVA PROFILE 1.0.2\0"));
+    }
+
+    #[test]
+    fn execute_zstd() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("heap.hprof");
+        let u = p.clone();
+        let w = p.clone();
+
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        expect_heap_used(&mut jni, &mut seq, c_management_factory, 42);
+
+        let m_get_platform_mxbean = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, _| ptr::eq(a_class, c_management_factory) && a_method == "getPlatformMXBean")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_platform_mxbean));
+
+        let c_hot_spot_diagnostic_mxbean = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "com/sun/management/HotSpotDiagnosticMXBean")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_hot_spot_diagnostic_mxbean));
+
+        let i_hot_spot_diagnostic_mxbean = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method_a()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(i_hot_spot_diagnostic_mxbean));
+
+        let m_dump_heap = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, _| ptr::eq(a_class, c_hot_spot_diagnostic_mxbean) && a_method == "dumpHeap")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_dump_heap));
+
+        let s_p = jni_type!(jstring);
+        jni
+            .expect_new_string_utf()
+            .withf_st(move |a_s| a_s == u.to_str().unwrap())
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| s_p);
+
+        let e = jni_type!(jobject);
+        jni
+            .expect_call_object_method_a()
+            .withf_st(move |&a_instance, &a_method, a_args| {
+                ptr::eq(a_instance, i_hot_spot_diagnostic_mxbean)
+                    && ptr::eq(a_method, m_dump_heap)
+                    && ptr::eq(unsafe { a_args[0].l }, s_p)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| {
+                fs::write(&w, "test-heap-dump").unwrap();
+                Option::Some(e)
+            });
+
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_compression: Some(Compression::Zstd), heap_dump_path: Some(p.clone()), ..Default::default() }, &Cell::new(false), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert!(!p.exists());
+        assert_eq!(report.document()["heapDump"], json!({ "path": t.path().join("heap.hprof.zst") }));
+    }
+
+    #[test]
+    fn execute_not_created() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("heap.hprof");
+        let u = p.clone();
+
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        expect_heap_used(&mut jni, &mut seq, c_management_factory, 42);
+
         let m_get_platform_mxbean = jni_type!(jmethodID);
         jni
             .expect_get_static_method()
@@ -164,7 +744,11 @@ mod tests {
             .in_sequence(&mut seq)
             .return_once_st(move |_, _, _| Option::Some(e));
 
-        HeapDump::new(&ManagementFactory::new(&jni), &t).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_path: Some(p.clone()), ..Default::default() }, &Cell::new(false), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert_eq!(report.document()["heapDump"], json!({ "skipped": format!("cannot create heap dump since {:?} was not created", p) }));
     }
 
     #[test]
@@ -182,6 +766,215 @@ mod tests {
             .in_sequence(&mut seq)
             .return_once_st(move |_| Option::Some(c_management_factory));
 
-        HeapDump::new(&ManagementFactory::new(&jni), &t).execute(JVMTI_RESOURCE_EXHAUSTED_THREADS as jint);
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_path: Some(t), ..Default::default() }, &Cell::new(false), &report)
+            .execute(JVMTI_RESOURCE_EXHAUSTED_THREADS as jint);
+
+        assert_eq!(report.document()["heapDump"], json!({ "skipped": "cannot create heap dump since the JVM is unable to create a thread" }));
+    }
+
+    #[test]
+    fn feature_versions() {
+        assert_eq!(feature_version("1.8"), 8);
+        assert_eq!(feature_version("11"), 11);
+        assert_eq!(feature_version("17.0.2"), 17);
+        assert_eq!(feature_version("bogus"), 0);
+    }
+
+    #[test]
+    fn gc_heap_dump_argument_lists() {
+        let p = PathBuf::from("/test/heap.hprof");
+
+        assert_eq!(gc_heap_dump_arguments(&p, true, false, None), vec!["/test/heap.hprof"]);
+        assert_eq!(gc_heap_dump_arguments(&p, true, true, None), vec!["-gz=1", "/test/heap.hprof"]);
+        assert_eq!(gc_heap_dump_arguments(&p, false, false, Some(4)), vec!["-all", "-parallel=4", "/test/heap.hprof"]);
+    }
+
+    #[test]
+    fn rotate() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let now = SystemTime::now();
+
+        for (i, (name, size)) in [("heap-4.hprof", 4), ("heap-3.hprof.gz", 3), ("heap-2.hprof", 2), ("heap-1.hprof", 1), ("other.hprof", 1)].iter().enumerate() {
+            let p = t.path().join(name);
+            fs::write(&p, "x".repeat(*size)).unwrap();
+            fs::File::options().write(true).open(&p).unwrap().set_modified(now - Duration::from_secs(i as u64 * 60)).unwrap();
+        }
+
+        let mut jni = MockJNI::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        let factory = ManagementFactory::new(&jni);
+        let heap_walked = Cell::new(false);
+        let h = HeapDump::new(&jvmti, &jni, &factory, &Parameters { heap_dump_max_count: Some(3), heap_dump_max_total_size: Some(8), heap_dump_path: Some(t.path().join("heap-%s.hprof")), ..Default::default() }, &heap_walked, &report);
+
+        assert_eq!(h.rotate(&t.path().join("heap-4.hprof")), vec![t.path().join("heap-2.hprof"), t.path().join("heap-1.hprof")]);
+        assert!(t.path().join("heap-4.hprof").exists());
+        assert!(t.path().join("heap-3.hprof.gz").exists());
+        assert!(t.path().join("other.hprof").exists());
+    }
+
+    #[test]
+    fn rotate_unreadable() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+
+        let mut jni = MockJNI::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        let factory = ManagementFactory::new(&jni);
+        let heap_walked = Cell::new(false);
+        let h = HeapDump::new(&jvmti, &jni, &factory, &Parameters { heap_dump_max_count: Some(1), heap_dump_path: Some(t.path().join("missing").join("heap-%s.hprof")), ..Default::default() }, &heap_walked, &report);
+
+        assert_eq!(h.rotate(&t.path().join("missing").join("heap-1.hprof")), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn execute_insufficient_space() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let f = t.path().join("fallback");
+
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        expect_heap_used(&mut jni, &mut seq, c_management_factory, i64::MAX);
+
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_fallback_directory: Some(f.clone()), heap_dump_path: Some(t.path().join("heap.hprof")), ..Default::default() }, &Cell::new(false), &report)
+            .execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        let reason = report.document()["heapDump"]["skipped"].as_str().unwrap().to_string();
+        assert!(reason.starts_with("cannot create heap dump since"), "{}", reason);
+        assert!(reason.contains(&format!("{} bytes", i64::MAX)), "{}", reason);
+        assert!(reason.contains(&format!("fallback {:?}", f)), "{}", reason);
+        assert!(f.is_dir());
+        assert!(!t.path().join("heap.hprof").exists());
+    }
+
+    #[test]
+    fn execute_fallback_not_created() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        fs::write(t.path().join("file"), "").unwrap();
+        let f = t.path().join("file").join("fallback");
+
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        expect_heap_used(&mut jni, &mut seq, c_management_factory, i64::MAX);
+
+        let jvmti = MockJVMTI::new();
+        let report = Report::new(Format::Json);
+        HeapDump::new(&jvmti, &jni, &ManagementFactory::new(&jni), &Parameters { heap_dump_fallback_directory: Some(f.clone()), heap_dump_path: Some(t.path().join("heap.hprof")), ..Default::default() }, &Cell::new(false), &report)
+            .execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        let reason = report.document()["heapDump"]["skipped"].as_str().unwrap().to_string();
+        assert!(reason.contains(&format!("fallback directory {:?} cannot be created", f)), "{}", reason);
+        assert!(!t.path().join("heap.hprof").exists());
+    }
+
+    #[test]
+    fn free_space() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+
+        assert!(super::free_space(t.path()).unwrap() > 0);
+        assert_eq!(super::free_space(&t.path().join("missing")), None);
+    }
+
+    fn expect_heap_used(jni: &mut MockJNI, seq: &mut Sequence, c_management_factory: jclass, used: i64) {
+        let m_get_memory_mxbean = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, _| ptr::eq(a_class, c_management_factory) && a_method == "getMemoryMXBean")
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_memory_mxbean));
+
+        let c_memory_mxbean = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/MemoryMXBean")
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_| Option::Some(c_memory_mxbean));
+
+        let i_memory_mxbean = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method_a()
+            .withf_st(move |_, &a_method, _| ptr::eq(a_method, m_get_memory_mxbean))
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_, _, _| Option::Some(i_memory_mxbean));
+
+        let m_get_heap_memory_usage = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, _| ptr::eq(a_class, c_memory_mxbean) && a_method == "getHeapMemoryUsage")
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_heap_memory_usage));
+
+        let c_memory_usage = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/MemoryUsage")
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_| Option::Some(c_memory_usage));
+
+        let i_memory_usage = jni_type!(jobject);
+        jni
+            .expect_call_object_method()
+            .withf_st(move |&a_instance, &a_method| ptr::eq(a_instance, i_memory_mxbean) && ptr::eq(a_method, m_get_heap_memory_usage))
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_, _| Option::Some(i_memory_usage));
+
+        let m_get_used = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, _| ptr::eq(a_class, c_memory_usage) && a_method == "getUsed")
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_used));
+
+        jni
+            .expect_call_long_method()
+            .withf_st(move |&a_instance, &a_method| ptr::eq(a_instance, i_memory_usage) && ptr::eq(a_method, m_get_used))
+            .times(1)
+            .in_sequence(seq)
+            .return_const(used);
     }
 }
@@ -14,25 +14,164 @@
  * limitations under the License.
  */
 
+use std::cell::Cell;
 use std::cmp;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use serde_json::{json, Value};
 
 use crate::action::Action;
 use crate::bindings::{jint, jlong, JNI_TRUE, jvmtiCapabilities};
+use crate::context::Parameters;
 use crate::heap::{ClassFormatter, Contents, Types};
+use crate::jni::JNI;
 use crate::jvmti::JVMTI;
+use crate::report::Report;
+
+// Instance count, total size and class rows of a class loader
+type Loader = (usize, jlong, Vec<(usize, jlong, String)>);
 
-pub struct HeapHistogram<'h, J: JVMTI> {
+pub struct HeapHistogram<'h, J: JVMTI, N: JNI> {
+    by_loader: bool,
+    by_module: bool,
+    heap_walked: &'h Cell<bool>,
+    jni: &'h N,
     jvmti: &'h J,
     max_entries: usize,
+    package_depth: usize,
+    report: &'h Report,
+    retained_sizes: bool,
 }
 
-impl<'h, J: JVMTI> HeapHistogram<'h, J> {
-    pub fn new(jvmti: &'h J, max_entries: usize) -> Self {
-        return Self { jvmti, max_entries };
+impl<'h, J: JVMTI, N: JNI> HeapHistogram<'h, J, N> {
+    pub fn new(jvmti: &'h J, jni: &'h N, parameters: &Parameters, heap_walked: &'h Cell<bool>, report: &'h Report) -> Self {
+        return Self {
+            by_loader: parameters.heap_histogram_by_loader,
+            by_module: parameters.heap_histogram_by_module,
+            heap_walked,
+            jni,
+            jvmti,
+            max_entries: parameters.heap_histogram_max_entries,
+            package_depth: parameters.heap_histogram_package_depth,
+            report,
+            retained_sizes: parameters.heap_histogram_retained_sizes,
+        };
+    }
+
+    fn print_by_loader(&self, contents: &Contents<J>, types: &Types<J>, formatter: &ClassFormatter) {
+        let mut loaders: HashMap<&String, Loader> = HashMap::new();
+
+        for s in contents.get_contents(usize::MAX) {
+            let l = loaders.entry(types.get_loader(s.tag)).or_insert((0, 0, Vec::new()));
+            l.0 += s.count;
+            l.1 += s.total_size;
+            if l.2.len() < self.max_entries {
+                l.2.push((s.count, s.total_size, formatter.format(types.get(s.tag))));
+            }
+        }
+
+        let mut sorted: Vec<(&String, Loader)> = loaders.into_iter().collect();
+        sorted.sort_unstable_by_key(|(_, l)| cmp::Reverse(l.1));
+
+        self.report.println("\n>>> Heap Histogram by Class Loader");
+        for (n, (c, s, rows)) in &sorted {
+            self.report.println(&format!("\n{}: {} instances, {} bytes", n, c, s));
+            self.print_table(("Instance Count", "Total Bytes", "Class Name"), rows);
+        }
+
+        self.report.set("heapHistogramByClassLoader", Value::Array(sorted.iter()
+            .map(|(n, (c, s, rows))| json!({
+                "classLoader": n,
+                "classes": Value::Array(rows.iter()
+                    .map(|(c, s, n)| json!({ "className": n, "instanceCount": c, "totalBytes": s }))
+                    .collect()),
+                "instanceCount": c,
+                "totalBytes": s,
+            }))
+            .collect()));
+    }
+
+    fn print_histogram(&self, contents: &Contents<J>, types: &Types<J>, formatter: &ClassFormatter) {
+        let formatted: Vec<(usize, jlong, String)> = contents.get_contents(self.max_entries).iter()
+            .map(|s| (s.count, s.total_size, formatter.format(types.get(s.tag))))
+            .collect();
+
+        self.report.println("\n>>> Heap Histogram");
+        self.print_table(("Instance Count", "Total Bytes", "Class Name"), &formatted);
+
+        self.report.set("heapHistogram", Value::Array(formatted.iter()
+            .map(|(c, s, n)| json!({ "className": n, "instanceCount": c, "totalBytes": s }))
+            .collect()));
+    }
+
+    fn print_retained_sizes(&self, contents: &Contents<J>, types: &Types<J>, formatter: &ClassFormatter) {
+        let classes: Vec<(jlong, jlong, String)> = contents.get_retained_classes(self.max_entries).iter()
+            .map(|r| (r.retained_size, r.size, formatter.format(types.get(r.tag))))
+            .collect();
+
+        self.report.println("\n>>> Retained Sizes by Class");
+        self.print_table(("Retained Bytes", "Total Bytes", "Class Name"), &classes);
+
+        let objects: Vec<(jlong, jlong, String)> = contents.get_retained_objects(self.max_entries).iter()
+            .map(|r| (r.retained_size, r.size, formatter.format(types.get(r.tag))))
+            .collect();
+
+        self.report.println("\n>>> Largest Retainers");
+        self.print_table(("Retained Bytes", "Shallow Bytes", "Class Name"), &objects);
+
+        self.report.set("retainedSizes", json!({
+            "classes": Value::Array(classes.iter()
+                .map(|(r, s, n)| json!({ "className": n, "retainedBytes": r, "totalBytes": s }))
+                .collect()),
+            "objects": Value::Array(objects.iter()
+                .map(|(r, s, n)| json!({ "className": n, "retainedBytes": r, "shallowBytes": s }))
+                .collect()),
+        }));
+    }
+
+    fn print_rollup<F: Fn(jlong) -> String>(&self, contents: &Contents<J>, title: &str, column: &str, key: &str, group: F) {
+        let mut groups: HashMap<String, (usize, jlong)> = HashMap::new();
+        let mut total = 0;
+
+        for s in contents.get_contents(usize::MAX) {
+            let g = groups.entry(group(s.tag)).or_insert((0, 0));
+            g.0 += s.count;
+            g.1 += s.total_size;
+            total += s.total_size;
+        }
+
+        let mut sorted: Vec<(String, (usize, jlong))> = groups.into_iter().collect();
+        sorted.sort_unstable_by_key(|(_, g)| cmp::Reverse(g.1));
+        sorted.truncate(self.max_entries);
+
+        let percent = |s: jlong| if total == 0 { 0.0 } else { s as f64 * 100.0 / total as f64 };
+
+        let rows: Vec<(jlong, String, String)> = sorted.iter()
+            .map(|(n, (_c, s))| (*s, format!("{:.1}%", percent(*s)), n.clone()))
+            .collect();
+
+        self.report.println(&format!("\n>>> {}", title));
+        self.print_table(("Total Bytes", "Percent", column), &rows);
+
+        self.report.set(key, Value::Array(sorted.iter()
+            .map(|(n, (c, s))| json!({ "instanceCount": c, "name": n, "percent": percent(*s), "totalBytes": s }))
+            .collect()));
+    }
+
+    fn print_table<A: Display, B: Display>(&self, headers: (&str, &str, &str), rows: &[(A, B, String)]) {
+        let max = rows.iter()
+            .fold(headers.2.len(), |max, (_a, _b, n)| cmp::max(max, n.len()));
+
+        self.report.println(&format!("| {} | {} | {}{} |", headers.0, headers.1, headers.2, " ".repeat(max - headers.2.len())));
+        self.report.println(&format!("| {} | {} | {} |", "-".repeat(headers.0.len()), "-".repeat(headers.1.len()), "-".repeat(max)));
+        for (a, b, n) in rows {
+            self.report.println(&format!("| {:<w0$} | {:<w1$} | {}{} |", a, b, n, " ".repeat(max - n.len()), w0 = headers.0.len(), w1 = headers.1.len()));
+        }
     }
 }
 
-impl<'h, J: JVMTI> Action for HeapHistogram<'h, J> {
+impl<'h, J: JVMTI, N: JNI> Action for HeapHistogram<'h, J, N> {
     fn execute(&self, _flags: jint) {
         let mut c: jvmtiCapabilities = Default::default();
         c.set_can_tag_objects(JNI_TRUE);
@@ -41,41 +180,75 @@ impl<'h, J: JVMTI> Action for HeapHistogram<'h, J> {
         let mut types = Types::new(self.jvmti);
         types.tag_classes();
 
-        let mut contents = Contents::new(self.jvmti);
-        contents.analyze_heap();
+        if self.by_loader {
+            types.resolve_loaders(self.jni);
+        }
+
+        if self.by_module {
+            types.resolve_modules(self.jni);
+        }
+
+        let mut contents = Contents::new(self.jvmti, self.retained_sizes);
+        contents.analyze_heap(self.heap_walked.replace(true));
 
         let f = ClassFormatter::new();
 
-        let mut max = 10;
-        let formatted: Vec<(usize, jlong, String)> = contents.get_contents(self.max_entries).iter()
-            .map(|s| (s.count, s.total_size, f.format(types.get(s.tag))))
-            .inspect(|(_c, _s, n)| max = cmp::max(max, n.len()))
-            .collect();
+        if self.by_loader {
+            self.print_by_loader(&contents, &types, &f);
+        } else {
+            self.print_histogram(&contents, &types, &f);
+        }
+
+        if self.package_depth > 0 {
+            self.print_rollup(&contents, "Heap by Package", "Package", "packageRollup", |tag| package(&f.format(types.get(tag)), self.package_depth));
+        }
 
-        println!("\n>>> Heap Histogram");
-        println!("| Instance Count | Total Bytes | Class Name{} |", " ".repeat(max - 10));
-        println!("| -------------- | ----------- | {} |", "-".repeat(max));
-        for (c, s, n) in formatted {
-            println!("| {:<14} | {:<11} | {}{} |", c, s, n, " ".repeat(max - n.len()));
+        if self.by_module {
+            self.print_rollup(&contents, "Heap by Module", "Module", "moduleRollup", |tag| types.get_module(tag).clone());
+        }
+
+        if self.retained_sizes {
+            self.print_retained_sizes(&contents, &types, &f);
         }
     }
 }
 
+fn package(class_name: &str, depth: usize) -> String {
+    let n = class_name.trim_end_matches("[]");
+
+    // Hidden classes, such as lambdas, have their address appended to the name, e.g. MyClass$$Lambda$14.0x0000000800c02a00
+    let n = match n.rsplit_once('.') {
+        Some((c, s)) if s.strip_prefix("0x").is_some_and(|a| !a.is_empty() && a.chars().all(|d| d.is_ascii_hexdigit())) => c,
+        _ => n,
+    };
+
+    return match n.rfind('.') {
+        Some(i) => n[..i].split('.').take(depth).collect::<Vec<&str>>().join("."),
+        None => String::from("<none>"),
+    };
+}
+
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
     use std::os::raw::c_void;
     use std::ptr;
 
     use mockall::Sequence;
+    use serde_json::json;
 
     use crate::action::Action;
-    use crate::action::heap_histogram::HeapHistogram;
+    use crate::action::heap_histogram::{HeapHistogram, package};
     use crate::bindings::{jclass, jint, jlong, JNI_TRUE, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, jvmtiHeapCallbacks, jvmtiHeapReferenceInfo, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_CLASS};
+    use crate::context::Parameters;
+    use crate::jni::MockJNI;
     use crate::jvmti::{ArrayPointerLoadedClassesIterator, MockJVMTI};
+    use crate::report::{Format, Report};
 
     #[test]
     fn execute() {
         let mut jvmti = MockJVMTI::new();
+        let jni = MockJNI::new();
         let mut seq = Sequence::new();
 
         jvmti
@@ -177,6 +350,25 @@ mod tests {
                 return ();
             });
 
-        HeapHistogram::new(&jvmti, 2).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+        let report = Report::new(Format::Json);
+        let heap_walked = Cell::new(false);
+        HeapHistogram::new(&jvmti, &jni, &Parameters { heap_histogram_max_entries: 2, ..Default::default() }, &heap_walked, &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert_eq!(report.document()["heapHistogram"], json!([
+            { "className": "charlie", "instanceCount": 2, "totalBytes": 60 },
+            { "className": "bravo", "instanceCount": 2, "totalBytes": 40 },
+        ]));
+        assert!(heap_walked.get());
+    }
+
+    #[test]
+    fn packages() {
+        assert_eq!(package("com.acme.cache.Entry", 2), "com.acme");
+        assert_eq!(package("com.acme.cache.Entry", 3), "com.acme.cache");
+        assert_eq!(package("com.acme.cache.Entry", 5), "com.acme.cache");
+        assert_eq!(package("com.acme.cache.Entry$Node[][]", 3), "com.acme.cache");
+        assert_eq!(package("org.cloudfoundry.MyClass$$Lambda$14.0x0000000800c02a00", 3), "org.cloudfoundry");
+        assert_eq!(package("org.cloudfoundry.MyClass$$Lambda$14.0x0000000800c02a00[]", 5), "org.cloudfoundry");
+        assert_eq!(package("byte[]", 3), "<none>");
     }
 }
@@ -0,0 +1,225 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{c_char, c_int, pid_t};
+use serde_json::{json, Value};
+
+use crate::action::Action;
+use crate::bindings::jint;
+use crate::report::Report;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Runs an external command once the other actions have completed. The command is started with posix_spawn, which does
+// not duplicate the JVM's address space and so works where fork() would exceed the overcommit limit. The details of
+// the event are passed in JVMKILL_EVENT_* environment variables.
+pub struct Hook<'h> {
+    arguments: Vec<String>,
+    report: &'h Report,
+    timeout: Duration,
+}
+
+impl<'h> Hook<'h> {
+    pub fn new(arguments: &[String], timeout: Duration, report: &'h Report) -> Self {
+        return Self { arguments: arguments.to_vec(), report, timeout };
+    }
+
+    fn environment(&self) -> Vec<CString> {
+        let d = self.report.document();
+        let e = &d["event"];
+
+        let mut variables: Vec<(OsString, OsString)> = vec![
+            ("JVMKILL_EVENT_CAUSE", &e["cause"]),
+            ("JVMKILL_EVENT_COUNT", &e["count"]),
+            ("JVMKILL_EVENT_COUNT_THRESHOLD", &e["countThreshold"]),
+            ("JVMKILL_EVENT_DESCRIPTION", &e["description"]),
+            ("JVMKILL_EVENT_FLAGS", &e["flags"]),
+            ("JVMKILL_EVENT_PID", &e["pid"]),
+            ("JVMKILL_EVENT_TIME", &e["time"]),
+            ("JVMKILL_EVENT_HEAP_DUMP_PATH", &d["heapDump"]["path"]),
+        ].into_iter()
+            .filter_map(|(k, v)| match v {
+                Value::Null => None,
+                Value::String(s) => Some((OsString::from(k), OsString::from(s))),
+                v => Some((OsString::from(k), OsString::from(v.to_string()))),
+            })
+            .collect();
+
+        // The agent's own variables are left out so that a JVM started by the command does not inherit its configuration
+        for (k, v) in std::env::vars_os() {
+            if !k.as_bytes().starts_with(b"JVMKILL_") {
+                variables.push((k, v));
+            }
+        }
+
+        return variables.into_iter()
+            .filter_map(|(k, v)| {
+                let mut s = k.into_vec();
+                s.push(b'=');
+                s.extend_from_slice(v.as_bytes());
+                return CString::new(s).ok();
+            })
+            .collect();
+    }
+
+    fn spawn(&self) -> Result<pid_t, String> {
+        let arguments: Vec<CString> = self.arguments.iter()
+            .enumerate()
+            .map(|(i, a)| CString::new(a.as_str()).map_err(|_| format!("invalid hook command argument {}", i)))
+            .collect::<Result<_, _>>()?;
+
+        let environment = self.environment();
+
+        let mut argv: Vec<*mut c_char> = arguments.iter().map(|a| a.as_ptr() as *mut c_char).collect();
+        argv.push(ptr::null_mut());
+
+        let mut envp: Vec<*mut c_char> = environment.iter().map(|e| e.as_ptr() as *mut c_char).collect();
+        envp.push(ptr::null_mut());
+
+        let mut pid: pid_t = 0;
+        let r = unsafe { libc::posix_spawnp(&mut pid, arguments[0].as_ptr(), ptr::null(), ptr::null(), argv.as_ptr(), envp.as_ptr()) };
+        if r != 0 {
+            return Err(format!("unable to run hook command {:?}: {}", self.arguments[0], io::Error::from_raw_os_error(r)));
+        }
+
+        return Ok(pid);
+    }
+
+    // Waits for the command to exit, killing it if it is still running when the timeout expires
+    fn wait(&self, pid: pid_t) -> Result<Outcome, String> {
+        let deadline = Instant::now() + self.timeout;
+        let mut status: c_int = 0;
+
+        loop {
+            let r = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+
+            if r == pid {
+                break;
+            } else if r < 0 {
+                return Err(format!("unable to wait for hook command: {}", io::Error::last_os_error()));
+            }
+
+            if Instant::now() >= deadline {
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, &mut status, 0);
+                }
+
+                return Ok(Outcome::TimedOut);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        return Ok(if libc::WIFEXITED(status) { Outcome::Exited(libc::WEXITSTATUS(status)) } else { Outcome::Killed(libc::WTERMSIG(status)) });
+    }
+}
+
+impl<'h> Action for Hook<'h> {
+    fn execute(&self, _flags: jint) {
+        if self.arguments.is_empty() {
+            return;
+        }
+
+        // The arguments may carry credentials, so only the program is reported
+        let command = self.arguments[0].clone();
+        self.report.println(&format!("\n>>> Hook\nRunning {}", command));
+
+        let (message, result) = match self.spawn().and_then(|pid| self.wait(pid)) {
+            Ok(Outcome::Exited(c)) => (format!("Hook exited with code {}", c), json!({ "command": command, "exitCode": c })),
+            Ok(Outcome::Killed(s)) => (format!("Hook was killed by signal {}", s), json!({ "command": command, "signal": s })),
+            Ok(Outcome::TimedOut) => (format!("Hook was killed after {} seconds", self.timeout.as_secs()), json!({ "command": command, "timedOut": true })),
+            Err(e) => (e.clone(), json!({ "command": command, "error": e })),
+        };
+
+        self.report.println(&message);
+        self.report.set("hook", result);
+    }
+}
+
+enum Outcome {
+    Exited(c_int),
+    Killed(c_int),
+    TimedOut,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::slice;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::action::Action;
+    use crate::action::hook::Hook;
+    use crate::bindings::{jint, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP};
+    use crate::context::Cause;
+    use crate::report::{Format, Report};
+
+    #[test]
+    fn execute() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let out = t.path().join("out");
+        let hook = script(t.path(), &format!("echo \"$1 $JVMKILL_EVENT_CAUSE $JVMKILL_EVENT_COUNT $JVMKILL_EVENT_COUNT_THRESHOLD $JVMKILL_EVENT_FLAGS $JVMKILL_EVENT_HEAP_DUMP_PATH ${{JVMKILL_HOOK_TEST:-unset}}\" > {}\nexit 3", out.display()));
+        std::env::set_var("JVMKILL_HOOK_TEST", "set");
+
+        let report = Report::new(Format::Json);
+        report.set_event(Cause::Heap, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint, "Java heap space", 1, 0);
+        report.set("heapDump", json!({ "path": "/test/heap.hprof" }));
+
+        Hook::new(&[hook.clone(), String::from("alpha bravo")], Duration::from_secs(10), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert_eq!(report.document()["hook"], json!({ "command": hook, "exitCode": 3 }));
+        assert_eq!(fs::read_to_string(&out).unwrap(), "alpha bravo heap 1 0 2 /test/heap.hprof unset\n");
+    }
+
+    #[test]
+    fn execute_missing_command() {
+        let report = Report::new(Format::Json);
+        Hook::new(&[String::from("/test/missing")], Duration::from_secs(10), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        let e = report.document()["hook"]["error"].as_str().unwrap().to_string();
+        assert!(e.starts_with("unable to run hook command \"/test/missing\""), "{}", e);
+    }
+
+    #[test]
+    fn execute_timeout() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let hook = script(t.path(), "sleep 10");
+
+        let report = Report::new(Format::Json);
+        Hook::new(slice::from_ref(&hook), Duration::from_millis(100), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert_eq!(report.document()["hook"], json!({ "command": hook, "timedOut": true }));
+    }
+
+    fn script(directory: &Path, body: &str) -> String {
+        let p = directory.join("hook.sh");
+        fs::write(&p, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&p, fs::Permissions::from_mode(0o755)).unwrap();
+        return p.to_str().unwrap().to_string();
+    }
+}
@@ -14,54 +14,417 @@
  * limitations under the License.
  */
 
+use std::time::Duration;
+
+use libc::c_int;
+use serde_json::{json, Value};
+
 use crate::action::Action;
+use crate::action::exit::{DefaultExit, Exit};
 use crate::action::signal::{DefaultSignal, Signal};
-use crate::bindings::jint;
+use crate::bindings::{jint, jvalue};
+use crate::context::{Cause, KillMode, Parameters};
+use crate::jni::JNI;
+use crate::metrics::Metrics;
+use crate::report::Report;
+use crate::sink;
+use crate::sink::{Severity, Sink};
+
+static EXIT: DefaultExit = DefaultExit;
 
-pub struct Kill<'k> {
-    pub signal: &'k dyn Signal,
+pub struct Kill<'k, J: JNI> {
+    dry_run: bool,
+    exit_code: c_int,
+    jni: &'k J,
+    metrics: &'k Metrics,
+    mode: KillMode,
+    report: &'k Report,
+    sink: Sink,
+    pub exit: &'k dyn Exit,
+    pub signals: Vec<Box<dyn Signal>>,
 }
 
-impl<'k> Kill<'k> {
-    pub fn new() -> Self {
-        return Self { signal: &DefaultSignal { signal: libc::SIGKILL, delay: false } };
+impl<'k, J: JNI> Kill<'k, J> {
+    pub fn new(jni: &'k J, parameters: &Parameters, cause: Cause, metrics: &'k Metrics, report: &'k Report) -> Self {
+        let mut signals: Vec<Box<dyn Signal>> = Vec::new();
+
+        if parameters.kill_signal != libc::SIGKILL {
+            signals.push(Box::new(DefaultSignal { signal: parameters.kill_signal, delay: Duration::from_secs(parameters.kill_grace_period as u64) }));
+        }
+
+        signals.push(Box::new(DefaultSignal { signal: libc::SIGKILL, delay: Duration::from_secs(0) }));
+
+        let exit_code = parameters.policy(cause).exit_code.unwrap_or(parameters.exit_code);
+
+        return Self { dry_run: parameters.dry_run, exit_code, jni, metrics, mode: parameters.kill_mode, report, sink: Sink::new(parameters), exit: &EXIT, signals };
+    }
+
+    fn announce(&self, message: &str) {
+        let d = self.report.document();
+        self.sink.summary(&d);
+
+        eprintln!("\n{}", message);
+        self.sink.log(Severity::Error, message, &sink::fields(&d["event"]));
+    }
+
+    fn dry_run(&self) {
+        let mode = match self.mode {
+            KillMode::Exit => "exit",
+            KillMode::Halt => "halt",
+            KillMode::Signal => "signal",
+        };
+
+        self.report.set("kill", json!({ "mode": mode, "suppressed": "dry run" }));
+        self.report.emit();
+
+        self.announce("jvmkill dry run: suppressed killing current process");
+    }
+
+    fn exit(&self) {
+        self.report.set("kill", json!({ "exitCode": self.exit_code, "mode": "exit" }));
+        self.report.emit();
+
+        self.announce(&format!("jvmkill is exiting current process with exit code {}", self.exit_code));
+        self.exit.exit(self.exit_code);
+    }
+
+    fn halt(&self) {
+        self.report.set("kill", json!({ "exitCode": self.exit_code, "mode": "halt" }));
+        self.report.emit();
+
+        self.announce(&format!("jvmkill is halting current process with exit code {}", self.exit_code));
+        self.runtime_halt();
+
+        // Runtime.halt never returns, so reaching here means that it could not be found or threw
+        let m = format!("jvmkill is unable to halt current process, exiting with exit code {}", self.exit_code);
+        eprintln!("{}", m);
+        self.sink.log(Severity::Error, &m, &sink::fields(&self.report.document()["event"]));
+        self.exit.exit(self.exit_code);
+    }
+
+    fn runtime_halt(&self) -> Option<()> {
+        let class = self.jni.find_class_optional("java/lang/Runtime")?;
+        let get_runtime = self.jni.get_static_method(class, "getRuntime", "()Ljava/lang/Runtime;")?;
+        let runtime = self.jni.call_static_object_method(class, get_runtime)?;
+        let halt = self.jni.get_method(class, "halt", "(I)V")?;
+
+        self.jni.call_void_method_a(runtime, halt, &[jvalue { i: self.exit_code }]);
+        return Some(());
+    }
+
+    fn signal(&self) {
+        self.report.set("kill", json!({
+            "mode": "signal",
+            "signals": Value::Array(self.signals.iter()
+                .map(|s| json!({ "delay": s.delay().as_secs(), "signal": s.signal() }))
+                .collect()),
+        }));
+        self.report.emit();
+
+        self.announce("jvmkill is killing current process");
+
+        for (i, s) in self.signals.iter().enumerate() {
+            if i > 0 {
+                let m = format!("jvmkill grace period expired, escalating to signal {}", s.signal());
+                eprintln!("{}", m);
+                self.sink.log(Severity::Error, &m, &sink::fields(&self.report.document()["event"]));
+            }
+
+            s.kill();
+        }
     }
 }
 
-impl<'k> Action for Kill<'k> {
+impl<'k, J: JNI> Action for Kill<'k, J> {
     fn execute(&self, _flags: jint) {
-        eprintln!("\njvmkill is killing current process");
-        self.signal.kill();
+        if self.dry_run {
+            return self.dry_run();
+        }
+
+        let d = self.report.document();
+        self.metrics.count("kills", &[("cause", d["event"]["cause"].as_str().unwrap_or("unknown"))]);
+        self.metrics.flush();
+
+        match self.mode {
+            KillMode::Exit => self.exit(),
+            KillMode::Halt => self.halt(),
+            KillMode::Signal => self.signal(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::ptr;
+    use std::time::Duration;
+
     use mockall::Sequence;
+    use serde_json::json;
 
     use crate::action::Action;
+    use crate::action::exit::MockExit;
     use crate::action::kill::Kill;
     use crate::action::signal::MockSignal;
+    use crate::bindings::{jclass, jmethodID, jobject};
+    use crate::context::{Cause, KillMode, Parameters};
+    use crate::jni::MockJNI;
+    use crate::metrics::Metrics;
+    use crate::report::{Format, Report};
 
     #[test]
     fn execute() {
+        let jni = MockJNI::new();
         let mut signal = MockSignal::new();
         let mut seq = Sequence::new();
 
+        signal
+            .expect_delay()
+            .return_const(Duration::from_secs(0));
+
+        signal
+            .expect_signal()
+            .return_const(libc::SIGKILL);
+
         signal
             .expect_kill()
             .times(1)
             .in_sequence(&mut seq)
             .return_const(());
 
-        let mut t = Kill::new();
+        let report = Report::new(Format::Json);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let mut t = Kill::new(&jni, &Parameters { kill_grace_period: 10, ..Default::default() }, Cause::Heap, &metrics, &report);
 
-        assert_eq!(t.signal.signal(), libc::SIGKILL);
-        assert_eq!(t.signal.delay(), false);
+        assert_eq!(t.signals.len(), 1);
+        assert_eq!(t.signals[0].signal(), libc::SIGKILL);
+        assert_eq!(t.signals[0].delay(), Duration::from_secs(0));
 
-        t.signal = &signal;
+        t.signals = vec![Box::new(signal)];
         t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "mode": "signal", "signals": [{ "delay": 0, "signal": libc::SIGKILL }] }));
     }
-}
 
+    #[test]
+    fn execute_dry_run() {
+        let jni = MockJNI::new();
+        let mut exit = MockExit::new();
+        let mut signal = MockSignal::new();
+
+        exit
+            .expect_exit()
+            .times(0);
+
+        signal
+            .expect_kill()
+            .times(0);
+
+        let report = Report::new(Format::Json);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let mut t = Kill::new(&jni, &Parameters { dry_run: true, ..Default::default() }, Cause::Heap, &metrics, &report);
+        t.exit = &exit;
+        t.signals = vec![Box::new(signal)];
+        t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "mode": "signal", "suppressed": "dry run" }));
+    }
+
+    #[test]
+    fn execute_escalation() {
+        let jni = MockJNI::new();
+        let mut first = MockSignal::new();
+        let mut second = MockSignal::new();
+        let mut seq = Sequence::new();
+
+        first
+            .expect_delay()
+            .return_const(Duration::from_secs(10));
+
+        first
+            .expect_signal()
+            .return_const(libc::SIGTERM);
+
+        first
+            .expect_kill()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        second
+            .expect_delay()
+            .return_const(Duration::from_secs(0));
+
+        second
+            .expect_signal()
+            .return_const(libc::SIGKILL);
+
+        second
+            .expect_kill()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let report = Report::new(Format::Json);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let mut t = Kill::new(&jni, &Parameters { kill_grace_period: 10, kill_signal: libc::SIGTERM, ..Default::default() }, Cause::Heap, &metrics, &report);
 
+        assert_eq!(t.signals.len(), 2);
+        assert_eq!(t.signals[0].signal(), libc::SIGTERM);
+        assert_eq!(t.signals[0].delay(), Duration::from_secs(10));
+        assert_eq!(t.signals[1].signal(), libc::SIGKILL);
+        assert_eq!(t.signals[1].delay(), Duration::from_secs(0));
+
+        t.signals = vec![Box::new(first), Box::new(second)];
+        t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "mode": "signal", "signals": [
+            { "delay": 10, "signal": libc::SIGTERM },
+            { "delay": 0, "signal": libc::SIGKILL },
+        ] }));
+    }
+
+    #[test]
+    fn execute_exit() {
+        let jni = MockJNI::new();
+        let mut exit = MockExit::new();
+
+        exit
+            .expect_exit()
+            .withf_st(|&a_code| a_code == 42)
+            .times(1)
+            .return_const(());
+
+        let report = Report::new(Format::Json);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let mut t = Kill::new(&jni, &Parameters { exit_code: 42, kill_mode: KillMode::Exit, ..Default::default() }, Cause::Heap, &metrics, &report);
+        t.exit = &exit;
+        t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "exitCode": 42, "mode": "exit" }));
+    }
+
+    #[test]
+    fn execute_exit_policy() {
+        let jni = MockJNI::new();
+        let mut exit = MockExit::new();
+
+        exit
+            .expect_exit()
+            .withf_st(|&a_code| a_code == 7)
+            .times(1)
+            .return_const(());
+
+        let mut p = Parameters { exit_code: 42, kill_mode: KillMode::Exit, ..Default::default() };
+        p.policies.entry(Cause::Metaspace).or_default().exit_code = Some(7);
+
+        let report = Report::new(Format::Json);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let mut t = Kill::new(&jni, &p, Cause::Metaspace, &metrics, &report);
+        t.exit = &exit;
+        t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "exitCode": 7, "mode": "exit" }));
+    }
+
+    #[test]
+    fn execute_halt() {
+        let mut exit = MockExit::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_runtime = jni_type!(jclass);
+        jni
+            .expect_find_class_optional()
+            .withf_st(move |a_class| a_class == "java/lang/Runtime")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_runtime));
+
+        let m_get_runtime = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_runtime)
+                    && a_method == "getRuntime"
+                    && a_signature == "()Ljava/lang/Runtime;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_runtime));
+
+        let i_runtime = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method()
+            .withf_st(move |&a_class, &a_method| ptr::eq(a_class, c_runtime) && ptr::eq(a_method, m_get_runtime))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| Option::Some(i_runtime));
+
+        let m_halt = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_runtime)
+                    && a_method == "halt"
+                    && a_signature == "(I)V"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_halt));
+
+        jni
+            .expect_call_void_method_a()
+            .withf_st(move |&a_instance, &a_method, a_args| {
+                ptr::eq(a_instance, i_runtime)
+                    && ptr::eq(a_method, m_halt)
+                    && unsafe { a_args[0].i } == 42
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        exit
+            .expect_exit()
+            .withf_st(|&a_code| a_code == 42)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let report = Report::new(Format::Json);
+        let mut t = Kill::new(&jni, &Parameters { exit_code: 42, kill_mode: KillMode::Halt, ..Default::default() }, Cause::Heap, &metrics, &report);
+        t.exit = &exit;
+        t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "exitCode": 42, "mode": "halt" }));
+    }
+
+    #[test]
+    fn execute_halt_not_found() {
+        let mut exit = MockExit::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        jni
+            .expect_find_class_optional()
+            .withf_st(move |a_class| a_class == "java/lang/Runtime")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::None);
+
+        exit
+            .expect_exit()
+            .withf_st(|&a_code| a_code == 42)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let report = Report::new(Format::Json);
+        let mut t = Kill::new(&jni, &Parameters { exit_code: 42, kill_mode: KillMode::Halt, ..Default::default() }, Cause::Heap, &metrics, &report);
+        t.exit = &exit;
+        t.execute(0);
+
+        assert_eq!(report.document()["kill"], json!({ "exitCode": 42, "mode": "halt" }));
+    }
+}
@@ -16,40 +16,62 @@
 
 use std::fmt::{Display, Error, Formatter};
 
+use serde_json::{json, Value};
+
 use crate::action;
 use crate::action::Action;
 use crate::bindings::jint;
 use crate::jmx::{ManagementFactory, MemoryPoolMXBean, MemoryUsage};
 use crate::jni::JNI;
+use crate::metrics::Metrics;
+use crate::report::Report;
 
 pub struct MemoryPools<'m, J: JNI> {
-    factory: &'m ManagementFactory<'m, J>
+    factory: &'m ManagementFactory<'m, J>,
+    metrics: &'m Metrics,
+    report: &'m Report,
 }
 
 impl<'m, J: JNI> MemoryPools<'m, J> {
-    pub fn new(factory: &'m ManagementFactory<J>) -> Self {
-        return Self { factory };
+    pub fn new(factory: &'m ManagementFactory<J>, metrics: &'m Metrics, report: &'m Report) -> Self {
+        return Self { factory, metrics, report };
     }
 }
 
 impl<'m, J: JNI> Action for MemoryPools<'m, J> {
     fn execute(&self, flags: jint) {
         if action::is_threads_exhausted(flags) {
-            eprintln!("cannot dump memory pools since the JVM is unable to create a thread");
+            self.report.skip("memoryPools", "cannot dump memory pools since the JVM is unable to create a thread");
             return;
         }
 
-        println!("\n>>> Memory Pools");
+        self.report.println("\n>>> Memory Pools");
 
-        println!("Memory usage:");
+        self.report.println("Memory usage:");
         let m = self.factory.get_memory_mxbean();
-        println!("{}", Statistics::from_usage(String::from("Heap memory"), m.get_heap_memory_usage()));
-        println!("{}", Statistics::from_usage(String::from("Non-heap memory"), m.get_non_heap_memory_usage()));
+        let usage = vec![
+            Statistics::from_usage(String::from("Heap memory"), m.get_heap_memory_usage()),
+            Statistics::from_usage(String::from("Non-heap memory"), m.get_non_heap_memory_usage()),
+        ];
+        for s in &usage {
+            self.report.println(&s.to_string());
+        }
+
+        self.report.println("\nMemory pool usage:");
+        let pools: Vec<Statistics> = self.factory.get_memory_pool_mxbeans().into_iter()
+            .map(Statistics::from_memory_pool)
+            .collect();
+        for s in &pools {
+            self.report.println(&s.to_string());
+        }
 
-        println!("\nMemory pool usage:");
-        for p in self.factory.get_memory_pool_mxbeans() {
-            println!("{}", Statistics::from_memory_pool(p));
+        for s in usage.iter().chain(&pools) {
+            s.send(self.metrics);
         }
+        self.metrics.flush();
+
+        self.report.set("memoryUsage", Value::Array(usage.iter().map(Statistics::to_json).collect()));
+        self.report.set("memoryPools", Value::Array(pools.iter().map(Statistics::to_json).collect()));
     }
 }
 
@@ -79,6 +101,25 @@ impl Statistics {
     fn is_near_max(&self) -> bool {
         return (self.committed as f32) / (self.max as f32) >= 0.95;
     }
+
+    fn send(&self, metrics: &Metrics) {
+        let tags = [("pool", self.name.as_str())];
+
+        metrics.gauge("memory.committed", self.committed, &tags);
+        metrics.gauge("memory.init", self.init, &tags);
+        metrics.gauge("memory.max", self.max, &tags);
+        metrics.gauge("memory.used", self.used, &tags);
+    }
+
+    fn to_json(&self) -> Value {
+        return json!({
+            "committed": self.committed,
+            "init": self.init,
+            "max": self.max,
+            "name": self.name,
+            "used": self.used,
+        });
+    }
 }
 
 impl Display for Statistics {
@@ -104,12 +145,16 @@ mod tests {
     use std::ptr;
 
     use mockall::Sequence;
+    use serde_json::json;
 
     use crate::action::Action;
     use crate::action::memory_pools::MemoryPools;
     use crate::bindings::{jclass, jint, jmethodID, jobject, jstring, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, JVMTI_RESOURCE_EXHAUSTED_THREADS};
+    use crate::context::Parameters;
     use crate::jmx::ManagementFactory;
     use crate::jni::MockJNI;
+    use crate::metrics::Metrics;
+    use crate::report::{Format, Report};
 
     #[test]
     fn execute() {
@@ -953,7 +998,14 @@ mod tests {
             .in_sequence(&mut seq)
             .return_once_st(move |_, _| 54);
 
-        MemoryPools::new(&ManagementFactory::new(&jni)).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+        let report = Report::new(Format::Json);
+        MemoryPools::new(&ManagementFactory::new(&jni), &Metrics::new(&Parameters { ..Default::default() }), &report).execute(JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP as jint);
+
+        assert_eq!(report.document()["memoryUsage"], json!([
+            { "committed": 11, "init": 12, "max": 13, "name": "Heap memory", "used": 14 },
+            { "committed": 21, "init": 22, "max": 23, "name": "Non-heap memory", "used": 24 },
+        ]));
+        assert_eq!(report.document()["memoryPools"].as_array().unwrap().len(), 3);
     }
 
     #[test]
@@ -969,6 +1021,9 @@ mod tests {
             .in_sequence(&mut seq)
             .return_once_st(move |_| Option::Some(c_management_factory));
 
-        MemoryPools::new(&ManagementFactory::new(&jni)).execute(JVMTI_RESOURCE_EXHAUSTED_THREADS as jint);
+        let report = Report::new(Format::Json);
+        MemoryPools::new(&ManagementFactory::new(&jni), &Metrics::new(&Parameters { ..Default::default() }), &report).execute(JVMTI_RESOURCE_EXHAUSTED_THREADS as jint);
+
+        assert_eq!(report.document()["memoryPools"], json!({ "skipped": "cannot dump memory pools since the JVM is unable to create a thread" }));
     }
 }
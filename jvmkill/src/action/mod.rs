@@ -14,23 +14,33 @@
  * limitations under the License.
  */
 
+use std::cell::Cell;
+use std::time::Duration;
+
 use crate::action::heap_dump::HeapDump;
 use crate::action::heap_histogram::HeapHistogram;
+use crate::action::hook::Hook;
 use crate::action::kill::Kill;
 use crate::action::memory_pools::MemoryPools;
 use crate::action::thread_dump::ThreadDump;
+use crate::action::webhook::Webhook;
 use crate::bindings::{jint, JVMTI_RESOURCE_EXHAUSTED_THREADS};
-use crate::context::Parameters;
+use crate::context::{ActionKind, Cause, Parameters};
 use crate::jmx::ManagementFactory;
 use crate::jni::JNI;
 use crate::jvmti::JVMTI;
+use crate::metrics::Metrics;
+use crate::report::Report;
 
+mod exit;
 mod heap_dump;
 mod heap_histogram;
+mod hook;
 mod kill;
 mod memory_pools;
 mod signal;
 mod thread_dump;
+mod webhook;
 
 pub trait Action {
     fn execute(&self, flags: jint);
@@ -41,25 +51,30 @@ pub struct Actions<'a> {
 }
 
 impl<'a> Actions<'a> {
-    pub fn new<N: JNI, V: JVMTI>(parameters: &Parameters, jvmti: &'a V, factory: &'a ManagementFactory<N>) -> Self {
-        let mut actions: Vec<Box<dyn Action>> = Vec::new();
-
-        if parameters.print_heap_histogram {
-            actions.push(Box::new(HeapHistogram::new(jvmti, parameters.heap_histogram_max_entries)));
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<N: JNI, V: JVMTI>(parameters: &Parameters, cause: Cause, jvmti: &'a V, jni: &'a N, factory: &'a ManagementFactory<N>, metrics: &'a Metrics, heap_walked: &'a Cell<bool>, report: &'a Report) -> Self {
+        let mut actions: Vec<Box<dyn Action>> = Actions::kinds(parameters, cause).into_iter()
+            .map(|kind| Actions::create(kind, parameters, jvmti, jni, factory, metrics, heap_walked, report))
+            .collect();
+
+        if let Some(u) = &parameters.webhook_url {
+            actions.push(Box::new(Webhook::new(u, Duration::from_secs(parameters.webhook_timeout as u64), report)));
         }
 
-        if parameters.print_memory_usage {
-            actions.push(Box::new(MemoryPools::new(factory)));
+        if let Some(c) = &parameters.hook_command {
+            actions.push(Box::new(Hook::new(c, Duration::from_secs(parameters.hook_timeout as u64), report)));
         }
 
-        actions.push(Box::new(ThreadDump::new()));
+        actions.push(Box::new(Kill::new(jni, parameters, cause, metrics, report)));
 
-        match &parameters.heap_dump_path {
-            Some(p) => actions.push(Box::new(HeapDump::new(factory, p))),
-            None => {}
-        };
+        return Self { actions };
+    }
 
-        actions.push(Box::new(Kill::new()));
+    // Actions run for an event that does not exceed the thresholds, without notifying hooks or killing the JVM
+    pub fn for_event<N: JNI, V: JVMTI>(parameters: &Parameters, jvmti: &'a V, jni: &'a N, factory: &'a ManagementFactory<N>, metrics: &'a Metrics, heap_walked: &'a Cell<bool>, report: &'a Report) -> Self {
+        let actions = parameters.event_actions.iter()
+            .map(|&kind| Actions::create(kind, parameters, jvmti, jni, factory, metrics, heap_walked, report))
+            .collect();
 
         return Self { actions };
     }
@@ -69,6 +84,41 @@ impl<'a> Actions<'a> {
             action.execute(flags);
         }
     }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create<N: JNI, V: JVMTI>(kind: ActionKind, parameters: &Parameters, jvmti: &'a V, jni: &'a N, factory: &'a ManagementFactory<N>, metrics: &'a Metrics, heap_walked: &'a Cell<bool>, report: &'a Report) -> Box<dyn Action + 'a> {
+        return match kind {
+            ActionKind::HeapDump => Box::new(HeapDump::new(jvmti, jni, factory, parameters, heap_walked, report)),
+            ActionKind::HeapHistogram => Box::new(HeapHistogram::new(jvmti, jni, parameters, heap_walked, report)),
+            ActionKind::MemoryPools => Box::new(MemoryPools::new(factory, metrics, report)),
+            ActionKind::ThreadDump => Box::new(ThreadDump::new(jvmti, parameters.thread_dump_mode, report)),
+        };
+    }
+
+    fn kinds(parameters: &Parameters, cause: Cause) -> Vec<ActionKind> {
+        match parameters.policy(cause).actions {
+            Some(a) => return a,
+            None => {}
+        }
+
+        let mut kinds = Vec::new();
+
+        if parameters.print_heap_histogram {
+            kinds.push(ActionKind::HeapHistogram);
+        }
+
+        if parameters.print_memory_usage {
+            kinds.push(ActionKind::MemoryPools);
+        }
+
+        kinds.push(ActionKind::ThreadDump);
+
+        if parameters.heap_dump_path.is_some() {
+            kinds.push(ActionKind::HeapDump);
+        }
+
+        return kinds;
+    }
 }
 
 pub fn is_threads_exhausted(flags: jint) -> bool {
@@ -78,16 +128,19 @@ pub fn is_threads_exhausted(flags: jint) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
     use std::path::PathBuf;
 
     use mockall::Sequence;
 
     use crate::action::Actions;
     use crate::bindings::jclass;
-    use crate::context::Parameters;
+    use crate::context::{Cause, Parameters};
     use crate::jmx::ManagementFactory;
     use crate::jni::MockJNI;
     use crate::jvmti::MockJVMTI;
+    use crate::metrics::Metrics;
+    use crate::report::{Format, Report};
 
     #[test]
     fn execute() {
@@ -104,9 +157,12 @@ mod tests {
             .return_once_st(move |_| Option::Some(c_management_factory));
 
         let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
 
         let p = Parameters { ..Default::default() };
-        let a = Actions::new(&p, &jvmti, &factory);
+        let report = Report::new(Format::Text);
+        let a = Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report);
 
         assert_eq!(a.actions.len(), 3);
     }
@@ -126,9 +182,12 @@ mod tests {
             .return_once_st(move |_| Option::Some(c_management_factory));
 
         let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
 
         let p = Parameters { print_heap_histogram: true, ..Default::default() };
-        let a = Actions::new(&p, &jvmti, &factory);
+        let report = Report::new(Format::Text);
+        let a = Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report);
 
         assert_eq!(a.actions.len(), 4);
     }
@@ -148,9 +207,12 @@ mod tests {
             .return_once_st(move |_| Option::Some(c_management_factory));
 
         let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
 
         let p = Parameters { print_memory_usage: false, ..Default::default() };
-        let a = Actions::new(&p, &jvmti, &factory);
+        let report = Report::new(Format::Text);
+        let a = Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report);
 
         assert_eq!(a.actions.len(), 2);
     }
@@ -170,10 +232,118 @@ mod tests {
             .return_once_st(move |_| Option::Some(c_management_factory));
 
         let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
 
         let p = Parameters { heap_dump_path: Some(PathBuf::from("test-dir")), ..Default::default() };
-        let a = Actions::new(&p, &jvmti, &factory);
+        let report = Report::new(Format::Text);
+        let a = Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report);
+
+        assert_eq!(a.actions.len(), 4);
+    }
+
+    #[test]
+    fn execute_hook_command() {
+        let jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
+
+        let p = Parameters { hook_command: Some(vec![String::from("test-hook")]), ..Default::default() };
+        let report = Report::new(Format::Text);
+        let a = Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report);
+
+        assert_eq!(a.actions.len(), 4);
+    }
+
+    #[test]
+    fn execute_webhook_url() {
+        let jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
+
+        let p = Parameters { webhook_url: Some(String::from("http://test")), ..Default::default() };
+        let report = Report::new(Format::Text);
+        let a = Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report);
 
         assert_eq!(a.actions.len(), 4);
     }
+
+    #[test]
+    fn execute_cause_actions() {
+        let jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
+
+        let p = create("printHeapHistogram=1,metaspace.actions=none,heap.actions=heapHistogram+memoryPools+threadDump");
+        let report = Report::new(Format::Text);
+
+        assert_eq!(Actions::new(&p, Cause::Metaspace, &jvmti, &jni, &factory, &metrics, &heap_walked, &report).actions.len(), 1);
+        assert_eq!(Actions::new(&p, Cause::Heap, &jvmti, &jni, &factory, &metrics, &heap_walked, &report).actions.len(), 4);
+        assert_eq!(Actions::new(&p, Cause::Threads, &jvmti, &jni, &factory, &metrics, &heap_walked, &report).actions.len(), 4);
+    }
+
+    #[test]
+    fn for_event() {
+        let jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let factory = ManagementFactory::new(&jni);
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+        let heap_walked = Cell::new(false);
+
+        let report = Report::new(Format::Text);
+
+        assert_eq!(Actions::for_event(&create("webhookUrl=http://test"), &jvmti, &jni, &factory, &metrics, &heap_walked, &report).actions.len(), 0);
+        assert_eq!(Actions::for_event(&create("eventActions=memoryPools+heapHistogram"), &jvmti, &jni, &factory, &metrics, &heap_walked, &report).actions.len(), 2);
+    }
+
+    fn create(s: &str) -> Parameters {
+        return Parameters::parse(s, Vec::new())
+            .expect("invalid parameters");
+    }
 }
@@ -22,7 +22,7 @@ use mockall::automock;
 
 #[cfg_attr(test, automock)]
 pub trait Signal {
-    fn delay(&self) -> bool;
+    fn delay(&self) -> Duration;
 
     fn kill(&self);
 
@@ -30,20 +30,20 @@ pub trait Signal {
 }
 
 pub struct DefaultSignal {
-    pub delay: bool,
+    pub delay: Duration,
     pub signal: c_int,
 }
 
 impl Signal for DefaultSignal {
-    fn delay(&self) -> bool {
+    fn delay(&self) -> Duration {
         return self.delay;
     }
 
     fn kill(&self) {
         unsafe { kill(getpid(), self.signal) };
 
-        if self.delay {
-            thread::sleep(Duration::from_millis(5000));
+        if self.delay > Duration::from_secs(0) {
+            thread::sleep(self.delay);
         }
     }
 
@@ -14,53 +14,374 @@
  * limitations under the License.
  */
 
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
 use crate::action::Action;
 use crate::action::signal::{DefaultSignal, Signal};
-use crate::bindings::jint;
+use crate::bindings::{jint, jlocation, JNI_TRUE, jthread, JVMTI_THREAD_STATE_ALIVE, JVMTI_THREAD_STATE_BLOCKED_ON_MONITOR_ENTER, JVMTI_THREAD_STATE_RUNNABLE, JVMTI_THREAD_STATE_TERMINATED, JVMTI_THREAD_STATE_WAITING, JVMTI_THREAD_STATE_WAITING_WITH_TIMEOUT, jvmtiCapabilities, jvmtiError, jvmtiError_JVMTI_ERROR_THREAD_NOT_ALIVE, jvmtiFrameInfo, jvmtiLineNumberEntry};
+use crate::context::ThreadDumpMode;
+use crate::heap::ClassFormatter;
+use crate::jvmti::{JVMTI, ThreadInfo};
+use crate::report::Report;
+
+const MAX_FRAMES: jint = 1024;
+
+static SIGQUIT: DefaultSignal = DefaultSignal { signal: libc::SIGQUIT, delay: Duration::from_secs(5) };
 
-pub struct ThreadDump<'t> {
+pub struct ThreadDump<'t, J: JVMTI> {
+    jvmti: &'t J,
+    mode: ThreadDumpMode,
+    report: &'t Report,
     pub signal: &'t dyn Signal,
 }
 
-impl<'t> ThreadDump<'t> {
-    pub fn new() -> Self {
-        return Self { signal: &DefaultSignal { signal: libc::SIGQUIT, delay: true } };
+impl<'t, J: JVMTI> ThreadDump<'t, J> {
+    pub fn new(jvmti: &'t J, mode: ThreadDumpMode, report: &'t Report) -> Self {
+        return Self { jvmti, mode, report, signal: &SIGQUIT };
+    }
+
+    fn dump_jvmti(&self) {
+        let mut c: jvmtiCapabilities = Default::default();
+        c.set_can_get_line_numbers(JNI_TRUE);
+        c.set_can_get_source_file_name(JNI_TRUE);
+        self.jvmti.add_capabilities(c);
+
+        let f = ClassFormatter::new();
+        let mut threads = Vec::new();
+
+        for t in self.jvmti.get_all_threads() {
+            let (info, state, trace) = match self.inspect(t) {
+                Ok(i) => i,
+                Err(r) => {
+                    if r != jvmtiError_JVMTI_ERROR_THREAD_NOT_ALIVE {
+                        eprintln!("Unable to dump thread: JVMTI error {}", r);
+                    }
+
+                    continue;
+                }
+            };
+
+            let state = thread_state(state);
+            let frames: Vec<String> = trace.iter()
+                .map(|frame| self.format_frame(&f, frame))
+                .collect();
+
+            self.report.println(&format!("\n\"{}\"{} prio={}", info.name, if info.daemon { " daemon" } else { "" }, info.priority));
+            self.report.println(&format!("   java.lang.Thread.State: {}", state));
+            for frame in &frames {
+                self.report.println(&format!("\tat {}", frame));
+            }
+
+            threads.push(json!({
+                "daemon": info.daemon,
+                "frames": frames,
+                "name": info.name,
+                "priority": info.priority,
+                "state": state,
+            }));
+        }
+
+        self.report.set("threadDump", json!({ "threads": Value::Array(threads) }));
+    }
+
+    fn dump_signal(&self) {
+        self.report.set("threadDump", json!({ "signal": self.signal.signal() }));
+        self.signal.kill();
+    }
+
+    fn format_frame(&self, formatter: &ClassFormatter, frame: &jvmtiFrameInfo) -> String {
+        let mut class = self.jvmti.get_method_declaring_class(frame.method);
+        let (signature, _) = self.jvmti.get_class_signature(&mut class);
+        let (name, _) = self.jvmti.get_method_name(frame.method);
+
+        let source = if frame.location == -1 {
+            String::from("Native Method")
+        } else {
+            let file = self.jvmti.get_source_file_name(class);
+            let line = line_number(&self.jvmti.get_line_number_table(frame.method), frame.location);
+
+            match (file, line) {
+                (Some(f), Some(l)) => format!("{}:{}", f, l),
+                (Some(f), None) => f,
+                (None, _) => String::from("Unknown Source"),
+            }
+        };
+
+        return format!("{}.{}({})", formatter.format(&signature), name, source);
+    }
+
+    // A thread may exit after the threads are listed, in which case it is left out of the dump
+    fn inspect(&self, thread: jthread) -> Result<(ThreadInfo, jint, Vec<jvmtiFrameInfo>), jvmtiError> {
+        return Ok((self.jvmti.get_thread_info(thread)?, self.jvmti.get_thread_state(thread)?, self.jvmti.get_stack_trace(thread, MAX_FRAMES)?));
     }
 }
 
-impl<'t> Action for ThreadDump<'t> {
+impl<'t, J: JVMTI> Action for ThreadDump<'t, J> {
     fn execute(&self, _flags: jint) {
-        println!("\n>>> Thread Dump");
+        self.report.println("\n>>> Thread Dump");
 
-        self.signal.kill();
+        match self.mode {
+            ThreadDumpMode::Jvmti => self.dump_jvmti(),
+            ThreadDumpMode::Signal => self.dump_signal(),
+        }
+    }
+}
+
+fn line_number(table: &[jvmtiLineNumberEntry], location: jlocation) -> Option<jint> {
+    return table.iter()
+        .filter(|e| e.start_location <= location)
+        .max_by_key(|e| e.start_location)
+        .map(|e| e.line_number);
+}
+
+fn thread_state(state: jint) -> &'static str {
+    let s = state as u32;
+
+    if s & JVMTI_THREAD_STATE_ALIVE == 0 {
+        return if s & JVMTI_THREAD_STATE_TERMINATED != 0 { "TERMINATED" } else { "NEW" };
+    } else if s & JVMTI_THREAD_STATE_RUNNABLE != 0 {
+        return "RUNNABLE";
+    } else if s & JVMTI_THREAD_STATE_BLOCKED_ON_MONITOR_ENTER != 0 {
+        return "BLOCKED";
+    } else if s & JVMTI_THREAD_STATE_WAITING_WITH_TIMEOUT != 0 {
+        return "TIMED_WAITING";
+    } else if s & JVMTI_THREAD_STATE_WAITING != 0 {
+        return "WAITING";
+    } else {
+        return "UNKNOWN";
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::ptr;
+    use std::time::Duration;
+
     use mockall::Sequence;
+    use serde_json::json;
 
-    use crate::action::signal::MockSignal;
-    use crate::action::thread_dump::ThreadDump;
     use crate::action::Action;
+    use crate::action::signal::MockSignal;
+    use crate::action::thread_dump::{line_number, thread_state, ThreadDump};
+    use crate::bindings::{jclass, jint, jmethodID, JNI_TRUE, jthread, JVMTI_THREAD_STATE_ALIVE, JVMTI_THREAD_STATE_RUNNABLE, JVMTI_THREAD_STATE_TERMINATED, JVMTI_THREAD_STATE_WAITING, JVMTI_THREAD_STATE_WAITING_INDEFINITELY, JVMTI_THREAD_STATE_WAITING_WITH_TIMEOUT, jvmtiError_JVMTI_ERROR_THREAD_NOT_ALIVE, jvmtiFrameInfo, jvmtiLineNumberEntry};
+    use crate::context::ThreadDumpMode;
+    use crate::jvmti::{MockJVMTI, ThreadInfo};
+    use crate::report::{Format, Report};
 
     #[test]
-    fn execute() {
+    fn execute_jvmti() {
+        let mut jvmti = MockJVMTI::new();
+        let mut seq = Sequence::new();
+
+        jvmti
+            .expect_add_capabilities()
+            .withf_st(|&a_capabilities| a_capabilities.can_get_line_numbers() == JNI_TRUE && a_capabilities.can_get_source_file_name() == JNI_TRUE)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let thread = jni_type!(jthread);
+        jvmti
+            .expect_get_all_threads()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move || vec![thread]);
+
+        jvmti
+            .expect_get_thread_info()
+            .withf_st(move |&a_thread| ptr::eq(a_thread, thread))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(ThreadInfo { daemon: true, name: String::from("test-thread"), priority: 5 }));
+
+        jvmti
+            .expect_get_thread_state()
+            .withf_st(move |&a_thread| ptr::eq(a_thread, thread))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok((JVMTI_THREAD_STATE_ALIVE | JVMTI_THREAD_STATE_RUNNABLE) as jint));
+
+        let m_alpha = jni_type!(jmethodID);
+        let m_bravo = jni_type!(jmethodID);
+        jvmti
+            .expect_get_stack_trace()
+            .withf_st(move |&a_thread, &a_max_frame_count| ptr::eq(a_thread, thread) && a_max_frame_count == 1024)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| Ok(vec![
+                jvmtiFrameInfo { method: m_alpha, location: -1 },
+                jvmtiFrameInfo { method: m_bravo, location: 12 },
+            ]));
+
+        let c_alpha = jni_type!(jclass);
+        jvmti
+            .expect_get_method_declaring_class()
+            .withf_st(move |&a_method| ptr::eq(a_method, m_alpha))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| c_alpha);
+
+        jvmti
+            .expect_get_class_signature()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_alpha))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const((String::from("Lorg/cloudfoundry/Alpha;"), String::new()));
+
+        jvmti
+            .expect_get_method_name()
+            .withf_st(move |&a_method| ptr::eq(a_method, m_alpha))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const((String::from("alpha"), String::from("()V")));
+
+        let c_bravo = jni_type!(jclass);
+        jvmti
+            .expect_get_method_declaring_class()
+            .withf_st(move |&a_method| ptr::eq(a_method, m_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| c_bravo);
+
+        jvmti
+            .expect_get_class_signature()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const((String::from("Lorg/cloudfoundry/Bravo;"), String::new()));
+
+        jvmti
+            .expect_get_method_name()
+            .withf_st(move |&a_method| ptr::eq(a_method, m_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const((String::from("bravo"), String::from("()V")));
+
+        jvmti
+            .expect_get_source_file_name()
+            .withf_st(move |&a_class| ptr::eq(a_class, c_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Some(String::from("Bravo.java")));
+
+        jvmti
+            .expect_get_line_number_table()
+            .withf_st(move |&a_method| ptr::eq(a_method, m_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(vec![
+                jvmtiLineNumberEntry { start_location: 0, line_number: 40 },
+                jvmtiLineNumberEntry { start_location: 10, line_number: 42 },
+                jvmtiLineNumberEntry { start_location: 20, line_number: 44 },
+            ]);
+
+        let report = Report::new(Format::Json);
+        ThreadDump::new(&jvmti, ThreadDumpMode::Jvmti, &report).execute(0);
+
+        assert_eq!(report.document()["threadDump"], json!({ "threads": [{
+            "daemon": true,
+            "frames": ["org.cloudfoundry.Alpha.alpha(Native Method)", "org.cloudfoundry.Bravo.bravo(Bravo.java:42)"],
+            "name": "test-thread",
+            "priority": 5,
+            "state": "RUNNABLE",
+        }] }));
+    }
+
+    #[test]
+    fn execute_jvmti_exited_thread() {
+        let mut jvmti = MockJVMTI::new();
+        let mut seq = Sequence::new();
+
+        jvmti
+            .expect_add_capabilities()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let thread = jni_type!(jthread);
+        jvmti
+            .expect_get_all_threads()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move || vec![thread]);
+
+        jvmti
+            .expect_get_thread_info()
+            .withf_st(move |&a_thread| ptr::eq(a_thread, thread))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(ThreadInfo { daemon: false, name: String::from("test-thread"), priority: 5 }));
+
+        jvmti
+            .expect_get_thread_state()
+            .withf_st(move |&a_thread| ptr::eq(a_thread, thread))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(JVMTI_THREAD_STATE_TERMINATED as jint));
+
+        jvmti
+            .expect_get_stack_trace()
+            .withf_st(move |&a_thread, _| ptr::eq(a_thread, thread))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(|_, _| Err(jvmtiError_JVMTI_ERROR_THREAD_NOT_ALIVE));
+
+        let report = Report::new(Format::Json);
+        ThreadDump::new(&jvmti, ThreadDumpMode::Jvmti, &report).execute(0);
+
+        assert_eq!(report.document()["threadDump"], json!({ "threads": [] }));
+    }
+
+    #[test]
+    fn execute_signal() {
+        let jvmti = MockJVMTI::new();
         let mut signal = MockSignal::new();
         let mut seq = Sequence::new();
 
+        signal
+            .expect_signal()
+            .return_const(libc::SIGQUIT);
+
         signal
             .expect_kill()
             .times(1)
             .in_sequence(&mut seq)
             .return_const(());
 
-        let mut t = ThreadDump::new();
+        let report = Report::new(Format::Json);
+        let mut t = ThreadDump::new(&jvmti, ThreadDumpMode::Signal, &report);
 
         assert_eq!(t.signal.signal(), libc::SIGQUIT);
-        assert_eq!(t.signal.delay(), true);
+        assert_eq!(t.signal.delay(), Duration::from_secs(5));
 
         t.signal = &signal;
         t.execute(0);
+
+        assert_eq!(report.document()["threadDump"], json!({ "signal": libc::SIGQUIT }));
+    }
+
+    #[test]
+    fn line_numbers() {
+        let table = vec![
+            jvmtiLineNumberEntry { start_location: 0, line_number: 40 },
+            jvmtiLineNumberEntry { start_location: 10, line_number: 42 },
+        ];
+
+        assert_eq!(line_number(&table, 0), Some(40));
+        assert_eq!(line_number(&table, 9), Some(40));
+        assert_eq!(line_number(&table, 10), Some(42));
+        assert_eq!(line_number(&table, 100), Some(42));
+        assert_eq!(line_number(&Vec::new(), 10), None);
+    }
+
+    #[test]
+    fn thread_states() {
+        assert_eq!(thread_state(0), "NEW");
+        assert_eq!(thread_state(JVMTI_THREAD_STATE_TERMINATED as jint), "TERMINATED");
+        assert_eq!(thread_state((JVMTI_THREAD_STATE_ALIVE | JVMTI_THREAD_STATE_RUNNABLE) as jint), "RUNNABLE");
+        assert_eq!(thread_state((JVMTI_THREAD_STATE_ALIVE | JVMTI_THREAD_STATE_WAITING | JVMTI_THREAD_STATE_WAITING_INDEFINITELY) as jint), "WAITING");
+        assert_eq!(thread_state((JVMTI_THREAD_STATE_ALIVE | JVMTI_THREAD_STATE_WAITING | JVMTI_THREAD_STATE_WAITING_WITH_TIMEOUT) as jint), "TIMED_WAITING");
     }
 }
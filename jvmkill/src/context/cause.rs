@@ -0,0 +1,119 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+
+use crate::bindings::{jint, JVMTI_RESOURCE_EXHAUSTED_THREADS};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Cause {
+    ArraySize,
+    DirectMemory,
+    Heap,
+    Metaspace,
+    Threads,
+}
+
+impl Cause {
+    pub const ALL: [Cause; 5] = [Cause::ArraySize, Cause::DirectMemory, Cause::Heap, Cause::Metaspace, Cause::Threads];
+
+    pub fn new(flags: jint, description: &str) -> Self {
+        let t = JVMTI_RESOURCE_EXHAUSTED_THREADS as jint;
+
+        if flags & t == t {
+            return Cause::Threads;
+        } else if description.contains("Requested array size exceeds VM limit") {
+            return Cause::ArraySize;
+        } else if description.contains("Metaspace") || description.contains("Compressed class space") {
+            return Cause::Metaspace;
+        } else if description.contains("Direct buffer memory") {
+            return Cause::DirectMemory;
+        } else {
+            return Cause::Heap;
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Cause::ArraySize => "arraySize",
+            Cause::DirectMemory => "directMemory",
+            Cause::Heap => "heap",
+            Cause::Metaspace => "metaspace",
+            Cause::Threads => "threads",
+        };
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        return match s {
+            "arraySize" => Some(Cause::ArraySize),
+            "directMemory" => Some(Cause::DirectMemory),
+            "heap" => Some(Cause::Heap),
+            "metaspace" => Some(Cause::Metaspace),
+            "threads" => Some(Cause::Threads),
+            _ => None,
+        };
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bindings::{jint, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR, JVMTI_RESOURCE_EXHAUSTED_THREADS};
+    use crate::context::Cause;
+
+    const HEAP: jint = (JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR | JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP) as jint;
+
+    #[test]
+    fn array_size() {
+        assert_eq!(Cause::new(HEAP, "Requested array size exceeds VM limit"), Cause::ArraySize);
+    }
+
+    #[test]
+    fn direct_memory() {
+        assert_eq!(Cause::new(JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR as jint, "Direct buffer memory"), Cause::DirectMemory);
+    }
+
+    #[test]
+    fn heap() {
+        assert_eq!(Cause::new(HEAP, "Java heap space"), Cause::Heap);
+        assert_eq!(Cause::new(HEAP, "GC overhead limit exceeded"), Cause::Heap);
+    }
+
+    #[test]
+    fn metaspace() {
+        assert_eq!(Cause::new(JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR as jint, "Metaspace"), Cause::Metaspace);
+        assert_eq!(Cause::new(JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR as jint, "Compressed class space"), Cause::Metaspace);
+    }
+
+    #[test]
+    fn names() {
+        for c in &Cause::ALL {
+            assert_eq!(Cause::parse(c.name()), Some(*c));
+        }
+
+        assert_eq!(Cause::parse("bogus"), None);
+    }
+
+    #[test]
+    fn threads() {
+        assert_eq!(Cause::new((JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR | JVMTI_RESOURCE_EXHAUSTED_THREADS) as jint, "unable to create new native thread"), Cause::Threads);
+    }
+}
@@ -0,0 +1,306 @@
+/*
+ * Copyright 2015-2019 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use std::fs;
+use std::path::Path;
+
+use crate::context::Cause;
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub line: usize,
+    pub value: String,
+}
+
+// Reads the options from a configuration file, either a properties file with the same keys as the inline options or a
+// TOML file. A TOML section either names a cause, so that [heap] count = 2 is heap.count, or is the start of an option
+// name, so that [webhook] url = ... is webhookUrl. Booleans become 1 or 0 and arrays of actions are joined with +. Lines
+// which cannot be parsed are returned as errors alongside the entries of the other lines, except for the lines of an
+// invalid section which are skipped rather than attributed to another section.
+pub fn load(path: &Path) -> (Vec<Entry>, Vec<String>) {
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => return (Vec::new(), vec![format!("unable to read configuration file {}: {}", path.display(), e)]),
+    };
+
+    let (entries, errors) = parse(&s);
+    return (entries, errors.into_iter().map(|(line, m)| format!("{}:{}: {}", path.display(), line, m)).collect());
+}
+
+fn key(section: &Option<String>, key: &str) -> String {
+    let s = match section {
+        Some(s) => s,
+        None => return String::from(key),
+    };
+
+    if Cause::parse(s).is_some() {
+        return format!("{}.{}", s, key);
+    }
+
+    let mut k = key.chars();
+    return match k.next() {
+        Some(c) => format!("{}{}{}", s, c.to_ascii_uppercase(), k.as_str()),
+        None => s.clone(),
+    };
+}
+
+fn parse(s: &str) -> (Vec<Entry>, Vec<(usize, String)>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let mut section = None;
+    let mut skip = false;
+
+    for (i, l) in s.lines().enumerate() {
+        let line = i + 1;
+        let l = l.trim();
+
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+
+        if l.starts_with('[') {
+            match parse_section(l) {
+                Ok(s) => {
+                    section = Some(s);
+                    skip = false;
+                }
+                Err(m) => {
+                    errors.push((line, m));
+                    skip = true;
+                }
+            }
+            continue;
+        }
+
+        if skip {
+            continue;
+        }
+
+        match parse_line(&section, l) {
+            Ok((k, value)) => entries.push(Entry { key: k, line, value }),
+            Err(m) => errors.push((line, m)),
+        }
+    }
+
+    return (entries, errors);
+}
+
+fn parse_line(section: &Option<String>, l: &str) -> Result<(String, String), String> {
+    let v: Vec<&str> = l.splitn(2, '=').collect();
+    if v.len() != 2 {
+        return Err(format!("invalid option: {}", l));
+    }
+
+    let k = v[0].trim();
+    if k.is_empty() {
+        return Err(format!("invalid key: {}", l));
+    }
+
+    let value = parse_value(v[1].trim())?;
+    if value.is_empty() {
+        return Err(format!("invalid value: {}", l));
+    }
+
+    return Ok((key(section, k), value));
+}
+
+fn parse_section(s: &str) -> Result<String, String> {
+    let name = strip_comment(s).strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .map(str::trim)
+        .unwrap_or_default();
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("invalid section: {}", s));
+    }
+
+    return Ok(String::from(name));
+}
+
+fn parse_string(s: &str, quote: char) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = s.char_indices().skip(1);
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => {
+                if !strip_comment(&s[i + 1..]).is_empty() {
+                    return Err(format!("unexpected text after string: {}", s));
+                }
+
+                return Ok(value);
+            }
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c)) if c == '"' || c == '\\' => value.push(c),
+                _ => return Err(format!("invalid escape in string: {}", s)),
+            },
+            c => value.push(c),
+        }
+    }
+
+    return Err(format!("unterminated string: {}", s));
+}
+
+fn parse_value(s: &str) -> Result<String, String> {
+    if s.starts_with('"') || s.starts_with('\'') {
+        return parse_string(s, s.chars().next().unwrap());
+    }
+
+    let s = strip_comment(s);
+
+    if s.starts_with('[') {
+        let items = s.strip_prefix('[')
+            .and_then(|i| i.strip_suffix(']'))
+            .ok_or_else(|| format!("unterminated array: {}", s))?;
+
+        let values = items.split(',')
+            .map(str::trim)
+            .filter(|i| !i.is_empty())
+            .map(parse_value)
+            .collect::<Result<Vec<String>, String>>()?;
+
+        return Ok(values.join("+"));
+    }
+
+    return Ok(String::from(match s {
+        "false" => "0",
+        "true" => "1",
+        _ => s,
+    }));
+}
+
+// A comment starts at a # which is preceded by whitespace, so that values such as URLs may contain #
+fn strip_comment(s: &str) -> &str {
+    let mut previous = ' ';
+
+    for (i, c) in s.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return s[..i].trim();
+        }
+
+        previous = c;
+    }
+
+    return s.trim();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use crate::context::config_file::{Entry, load, parse};
+
+    #[test]
+    fn parses_properties() {
+        assert_eq!(parse("# comment\n\ncount=2\nheap.count = 3\nwebhookUrl=http://test/#fragment\n").0, vec![
+            entry("count", 3, "2"),
+            entry("heap.count", 4, "3"),
+            entry("webhookUrl", 5, "http://test/#fragment"),
+        ]);
+    }
+
+    #[test]
+    fn parses_toml() {
+        let s = r#"
+count = 2 # two events
+dryRun = true
+
+[heap]
+actions = ["heapHistogram", "memoryPools"]
+time = "5m"
+
+[webhook]
+url = 'http://test'
+"#;
+
+        assert_eq!(parse(s).0, vec![
+            entry("count", 2, "2"),
+            entry("dryRun", 3, "1"),
+            entry("heap.actions", 6, "heapHistogram+memoryPools"),
+            entry("heap.time", 7, "5m"),
+            entry("webhookUrl", 10, "http://test"),
+        ]);
+    }
+
+    #[test]
+    fn parses_strings() {
+        assert_eq!(parse(r#"hookCommand = "a \"b\" \\c" # comment"#).0, vec![entry("hookCommand", 1, r#"a "b" \c"#)]);
+        assert_eq!(parse(r#"hookCommand = 'a \b'"#).0, vec![entry("hookCommand", 1, r#"a \b"#)]);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert_eq!(parse("count=2\ncount").1, vec![(2, String::from("invalid option: count"))]);
+        assert_eq!(parse("= 2").1, vec![(1, String::from("invalid key: = 2"))]);
+        assert_eq!(parse("\n\ncount = ").1, vec![(3, String::from("invalid value: count ="))]);
+        assert_eq!(parse("[heap.policy]").1, vec![(1, String::from("invalid section: [heap.policy]"))]);
+        assert_eq!(parse("hookCommand = \"a").1, vec![(1, String::from("unterminated string: \"a"))]);
+        assert_eq!(parse("hookCommand = \"a\" b").1, vec![(1, String::from("unexpected text after string: \"a\" b"))]);
+        assert_eq!(parse("heap.actions = [a").1, vec![(1, String::from("unterminated array: [a"))]);
+    }
+
+    #[test]
+    fn collects_invalid_lines() {
+        assert_eq!(parse("count\ntime = 5\n= 2\n[heap.policy]\ncount = 2\n[heap]\ncount = 3\n"), (
+            vec![entry("time", 2, "5"), entry("heap.count", 7, "3")],
+            vec![
+                (1, String::from("invalid option: count")),
+                (3, String::from("invalid key: = 2")),
+                (4, String::from("invalid section: [heap.policy]")),
+            ],
+        ));
+    }
+
+    #[test]
+    fn loads() {
+        let t = TempDir::new("jvmkill").unwrap();
+        let path = t.path().join("jvmkill.toml");
+        fs::write(&path, "count = 2\n").unwrap();
+
+        assert_eq!(load(&path), (vec![entry("count", 1, "2")], vec![]));
+    }
+
+    #[test]
+    fn load_invalid() {
+        let t = TempDir::new("jvmkill").unwrap();
+        let path = t.path().join("jvmkill.toml");
+        fs::write(&path, "count = 2\ntime\n").unwrap();
+
+        assert_eq!(load(&path), (vec![entry("count", 1, "2")], vec![format!("{}:2: invalid option: time", path.display())]));
+    }
+
+    #[test]
+    fn load_missing() {
+        let t = TempDir::new("jvmkill").unwrap();
+        let path = t.path().join("jvmkill.toml");
+
+        let (entries, errors) = load(&path);
+
+        assert_eq!(entries, vec![]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(&format!("unable to read configuration file {}: ", path.display())), "{}", errors[0]);
+    }
+
+    fn entry(key: &str, line: usize, value: &str) -> Entry {
+        return Entry { key: String::from(key), line, value: String::from(value) };
+    }
+}
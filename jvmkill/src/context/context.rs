@@ -14,54 +14,206 @@
  * limitations under the License.
  */
 
-use std::ops::Sub;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::os::raw::c_char;
 use std::time::{Duration, Instant};
 
+use crate::context::Cause;
 use crate::context::events::Events;
 use crate::context::Parameters;
+use crate::context::parameters::ParameterError;
+use crate::metrics::Metrics;
+use crate::sink::{Severity, Sink};
+
+// What recording an event led to: only a Recorded event runs the eventActions and only a Triggered one runs the
+// actions that end with killing the JVM
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    CoolingDown,
+    Ignored,
+    Recorded,
+    Triggered,
+}
 
 pub struct Context {
+    cause_events: HashMap<Cause, Events>,
     events: Events,
+    last_triggered: Option<Instant>,
+    pub count: usize,
+    pub count_threshold: usize,
+    // Whether an earlier heap walk may have left tags on the heap's objects
+    pub heap_walked: Cell<bool>,
+    pub metrics: Metrics,
     pub parameters: Parameters,
+    pub sink: Sink,
 }
 
 impl Context {
-    pub fn new(options: *const c_char) -> Context {
-        let p = Parameters::new(options);
-        let e = Events::new(p.count_threshold);
+    pub fn new(options: *const c_char) -> Result<Context, ParameterError> {
+        let p = Parameters::new(options)?;
+        let e = Events::new(p.rate_limit, p.count_threshold, p.time_threshold);
+        let m = Metrics::new(&p);
+        let s = Sink::new(&p);
 
-        return Context { events: e, parameters: p };
+        return Ok(Context { cause_events: HashMap::new(), events: e, last_triggered: None, count: 0, count_threshold: 0, heap_walked: Cell::new(false), metrics: m, parameters: p, sink: s });
     }
 
-    pub fn record(&mut self) -> bool {
-        self.events.record();
-        let count = self.events.events_since(Instant::now().sub(Duration::from_secs(self.parameters.time_threshold as u64)));
-        eprintln!("Resource Exhausted! ({}/{})", count, self.parameters.count_threshold);
-        return count > self.parameters.count_threshold;
+    pub fn record(&mut self, cause: Cause) -> Outcome {
+        self.metrics.event(cause);
+
+        let policy = self.parameters.policy(cause);
+
+        if policy.ignore {
+            let m = format!("Resource Exhausted! ({} ignored)", cause);
+            eprintln!("{}", m);
+            self.sink.log(Severity::Warning, &m, &[("cause", String::from(cause.name())), ("pid", std::process::id().to_string())]);
+            return Outcome::Ignored;
+        }
+
+        let count_threshold = policy.count_threshold.unwrap_or(self.parameters.count_threshold);
+        let rate_limit = self.parameters.rate_limit;
+        let time_threshold = policy.time_threshold.unwrap_or(self.parameters.time_threshold);
+
+        let events = if policy.count_threshold.is_none() && policy.time_threshold.is_none() {
+            &mut self.events
+        } else {
+            self.cause_events.entry(cause).or_insert_with(|| Events::new(rate_limit, count_threshold, time_threshold))
+        };
+
+        let count = events.record();
+        let m = format!("Resource Exhausted! ({}/{})", count, count_threshold);
+        eprintln!("{}", m);
+        self.sink.log(Severity::Error, &m, &[
+            ("cause", String::from(cause.name())),
+            ("count", count.to_string()),
+            ("countThreshold", count_threshold.to_string()),
+            ("pid", std::process::id().to_string()),
+        ]);
+
+        self.count = count;
+        self.count_threshold = count_threshold;
+
+        if count <= count_threshold {
+            return Outcome::Recorded;
+        }
+
+        // The JVM survives a dry run, so without a cooldown every following event would repeat the diagnostics
+        if self.parameters.dry_run {
+            let now = Instant::now();
+            let cooldown = Duration::from_secs(self.parameters.dry_run_cooldown as u64);
+
+            match self.last_triggered {
+                Some(t) if now.duration_since(t) < cooldown => {
+                    eprintln!("jvmkill dry run: skipping actions during cooldown");
+                    return Outcome::CoolingDown;
+                }
+                _ => self.last_triggered = Some(now),
+            }
+        }
+
+        return Outcome::Triggered;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::ffi::CString;
+    use std::net::UdpSocket;
+    use std::time::Duration;
 
-    use crate::context::Context;
+    use crate::context::{Cause, Context, Outcome};
 
     #[test]
     fn does_not_trigger() {
-        assert_eq!(create("count=100,time=100").record(), false);
+        assert_eq!(create("count=100,time=100").record(Cause::Heap), Outcome::Recorded);
+    }
+
+    #[test]
+    fn dry_run_cooldown() {
+        let mut c = create("count=0,dryRun=1,dryRunCooldown=100");
+
+        assert_eq!(c.record(Cause::Heap), Outcome::Triggered);
+        assert_eq!(c.record(Cause::Heap), Outcome::CoolingDown);
+    }
+
+    #[test]
+    fn dry_run_without_cooldown() {
+        let mut c = create("count=0,dryRun=1,dryRunCooldown=0");
+
+        assert_eq!(c.record(Cause::Heap), Outcome::Triggered);
+        assert_eq!(c.record(Cause::Heap), Outcome::Triggered);
+    }
+
+    #[test]
+    fn decays_events() {
+        let mut c = create("count=1,time=1h,rateLimit=decay");
+
+        assert_eq!(c.record(Cause::Threads), Outcome::Recorded);
+        assert_eq!(c.record(Cause::Threads), Outcome::Triggered);
+    }
+
+    #[test]
+    fn ignores_cause() {
+        let mut c = create("count=0,arraySize.ignore=1");
+
+        assert_eq!(c.record(Cause::ArraySize), Outcome::Ignored);
+        assert_eq!(c.record(Cause::Heap), Outcome::Triggered);
+    }
+
+    #[test]
+    fn shares_events_between_causes() {
+        let mut c = create("count=1,time=100");
+
+        assert_eq!(c.record(Cause::Heap), Outcome::Recorded);
+        assert_eq!(c.record(Cause::Metaspace), Outcome::Triggered);
+    }
+
+    #[test]
+    fn separates_events_for_cause_thresholds() {
+        let mut c = create("count=1,time=100,metaspace.count=0");
+
+        assert_eq!(c.record(Cause::Heap), Outcome::Recorded);
+        assert_eq!(c.record(Cause::Metaspace), Outcome::Triggered);
+        assert_eq!(c.record(Cause::DirectMemory), Outcome::Triggered);
+    }
+
+    #[test]
+    fn records_metrics() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        let mut c = create(&format!("count=1,heap.ignore=1,statsdAddress={}", socket.local_addr().unwrap()));
+        c.record(Cause::Heap);
+        c.record(Cause::Threads);
+
+        let mut b = [0; 1024];
+        let n = socket.recv(&mut b).unwrap();
+        assert_eq!(&b[..n], b"jvmkill.events:1|c|#cause:heap");
+        let n = socket.recv(&mut b).unwrap();
+        assert_eq!(&b[..n], b"jvmkill.events:1|c|#cause:threads");
+    }
+
+    #[test]
+    fn records_counts() {
+        let mut c = create("count=1,time=100");
+
+        c.record(Cause::Heap);
+        c.record(Cause::Heap);
+
+        assert_eq!((c.count, c.count_threshold), (2, 1));
     }
 
     #[test]
     fn triggers() {
-        assert_eq!(create("count=0").record(), true);
+        assert_eq!(create("count=0").record(Cause::Heap), Outcome::Triggered);
     }
 
     fn create(s: &str) -> Context {
         let options = CString::new(s)
             .expect("cannot convert to CString");
 
-        return Context::new(options.as_ptr());
+        return Context::new(options.as_ptr())
+            .expect("invalid parameters");
     }
 }
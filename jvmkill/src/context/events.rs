@@ -14,62 +14,159 @@
  * limitations under the License.
  */
 
-use std::time::Instant;
 
-pub struct Events {
-    events: circular_queue::CircularQueue<Instant>
+use std::time::{Duration, Instant};
+
+use circular_queue::CircularQueue;
+
+use crate::context::RateLimit;
+
+// Rates recent events with one of the rate limiting algorithms, as a whole number of events that is compared with count
+pub enum Events {
+    // An exponentially decaying score, to which each event adds one: events in a burst add up to nearly their number,
+    // while events spread over much more than the time constant barely accumulate
+    Decay { last: Option<Instant>, score: f64, time: Duration },
+
+    // The number of events in the window, holding at most one event more than can be tolerated
+    Sliding { events: CircularQueue<Instant>, time: Duration },
+
+    // The level of a leaky bucket, the equivalent of a token bucket that refills count tokens per time, to which each
+    // event adds one
+    TokenBucket { last: Option<Instant>, level: f64, rate: f64 },
 }
 
 impl Events {
-    pub fn new(limit: usize) -> Events {
-        return Events { events: circular_queue::CircularQueue::with_capacity(limit + 1) };
+    pub fn new(rate_limit: RateLimit, limit: usize, time: Duration) -> Events {
+        return match rate_limit {
+            RateLimit::Decay => Events::Decay { last: None, score: 0.0, time },
+            RateLimit::Sliding => Events::Sliding { events: CircularQueue::with_capacity(limit + 1), time },
+            // Without a time, the bucket empties between any two events rather than leaking at a NaN rate
+            RateLimit::TokenBucket => Events::TokenBucket {
+                last: None,
+                level: 0.0,
+                rate: if time == Duration::from_secs(0) { f64::INFINITY } else { limit as f64 / time.as_secs_f64() },
+            },
+        };
     }
 
-    pub fn events_since(&mut self, since: Instant) -> usize {
-        return self.events.iter()
-            .filter(|&&i| i > since)
-            .count();
+    pub fn record(&mut self) -> usize {
+        return self.record_at(Instant::now());
     }
 
-    pub fn record(&mut self) {
-        self.events.push(Instant::now());
+    fn record_at(&mut self, now: Instant) -> usize {
+        match self {
+            Events::Decay { last, score, time } => {
+                let elapsed = elapsed(*last, now).as_secs_f64();
+                let decay = if elapsed == 0.0 { 1.0 } else { (-elapsed / time.as_secs_f64()).exp() };
+
+                *score = *score * decay + 1.0;
+                *last = Some(now);
+                return score.round() as usize;
+            }
+
+            Events::Sliding { events, time } => {
+                events.push(now);
+
+                return match now.checked_sub(*time) {
+                    Some(since) => events.iter().filter(|&&i| i > since).count(),
+                    None => events.len(),
+                };
+            }
+
+            Events::TokenBucket { last, level, rate } => {
+                let elapsed = elapsed(*last, now).as_secs_f64();
+                let leaked = if elapsed == 0.0 { 0.0 } else { *rate * elapsed };
+
+                *level = (*level - leaked).max(0.0) + 1.0;
+                *last = Some(now);
+                return level.round() as usize;
+            }
+        }
     }
 }
 
+fn elapsed(last: Option<Instant>, now: Instant) -> Duration {
+    return match last {
+        Some(l) => now.saturating_duration_since(l),
+        None => Duration::from_secs(0),
+    };
+}
+
 #[cfg(test)]
 mod tests {
-    use std::ops::{Add, Sub};
+    use std::ops::Add;
     use std::time::{Duration, Instant};
 
     use crate::context::events::Events;
+    use crate::context::RateLimit;
+
+    #[test]
+    fn decays_events() {
+        let mut e = Events::new(RateLimit::Decay, 1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_eq!(e.record_at(now), 1);
+        assert_eq!(e.record_at(now.add(Duration::from_secs(600))), 1);
+        assert_eq!(e.record_at(now.add(Duration::from_millis(600_010))), 2);
+        assert_eq!(e.record_at(now.add(Duration::from_millis(600_020))), 3);
+    }
+
+    #[test]
+    fn decays_without_time() {
+        let mut e = Events::new(RateLimit::Decay, 1, Duration::from_secs(0));
+        let now = Instant::now();
+
+        assert_eq!(e.record_at(now), 1);
+        assert_eq!(e.record_at(now.add(Duration::from_millis(1))), 1);
+    }
 
     #[test]
     fn filters_events() {
-        let mut e = Events::new(1);
+        let mut e = Events::new(RateLimit::Sliding, 1, Duration::from_millis(500));
+        let now = Instant::now();
 
-        e.record();
+        assert_eq!(e.record_at(now), 1);
+        assert_eq!(e.record_at(now.add(Duration::from_millis(400))), 2);
+        assert_eq!(e.record_at(now.add(Duration::from_millis(1000))), 1);
+    }
 
-        assert_eq!(e.events_since(Instant::now().add(Duration::from_secs(10))), 0);
-        assert_eq!(e.events_since(Instant::now().sub(Duration::from_secs(10))), 1);
+    #[test]
+    fn leaks_bucket() {
+        let mut e = Events::new(RateLimit::TokenBucket, 2, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert_eq!(e.record_at(now), 1);
+        assert_eq!(e.record_at(now), 2);
+        assert_eq!(e.record_at(now.add(Duration::from_secs(5))), 2);
+        assert_eq!(e.record_at(now.add(Duration::from_secs(5))), 3);
+        assert_eq!(e.record_at(now.add(Duration::from_secs(60))), 1);
+    }
+
+    #[test]
+    fn leaks_bucket_without_time() {
+        let mut e = Events::new(RateLimit::TokenBucket, 0, Duration::from_secs(0));
+        let now = Instant::now();
+
+        assert_eq!(e.record_at(now), 1);
+        assert_eq!(e.record_at(now), 2);
+        assert_eq!(e.record_at(now.add(Duration::from_millis(1))), 1);
     }
 
     #[test]
     fn records_beyond_limit() {
-        let mut e = Events::new(2);
+        let mut e = Events::new(RateLimit::Sliding, 2, Duration::from_secs(10));
 
-        for _ in 1..5 {
+        for _ in 1..4 {
             e.record();
         }
 
-        assert_eq!(e.events_since(Instant::now().sub(Duration::from_secs(10))), 3)
+        assert_eq!(e.record(), 3)
     }
 
     #[test]
     fn records_events() {
-        let mut e = Events::new(1);
-
-        e.record();
+        let mut e = Events::new(RateLimit::Sliding, 1, Duration::from_secs(10));
 
-        assert_eq!(e.events_since(Instant::now().sub(Duration::from_secs(10))), 1)
+        assert_eq!(e.record(), 1)
     }
 }
@@ -14,9 +14,12 @@
  * limitations under the License.
  */
 
-pub use context::Context;
-pub use parameters::Parameters;
+pub use cause::Cause;
+pub use context::{Context, Outcome};
+pub use parameters::{ActionKind, Compression, HeapDumpWriter, KillMode, LogSink, Parameters, RateLimit, ThreadDumpMode};
 
+mod cause;
+mod config_file;
 mod context;
 mod events;
 mod parameters;
\ No newline at end of file
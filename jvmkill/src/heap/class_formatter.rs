@@ -22,7 +22,7 @@ pub struct ClassFormatter {
 
 impl ClassFormatter {
     pub fn new() -> Self {
-        let pattern = Regex::new(r"(\[*)([BCDFIJLSZ])(?:([a-zA-z/$0-9]+);)?").unwrap();
+        let pattern = Regex::new(r"(\[*)([BCDFIJLSZ])(?:([^;]+);)?").unwrap();
         return Self { pattern };
     }
 
@@ -82,6 +82,11 @@ mod tests {
         assert_eq!(ClassFormatter::new().format(&String::from("Lorg/cloudfoundry/MyClass;")), "org.cloudfoundry.MyClass");
     }
 
+    #[test]
+    fn hidden_class() {
+        assert_eq!(ClassFormatter::new().format(&String::from("Lorg/cloudfoundry/MyClass$$Lambda$14.0x0000000800c02a00;")), "org.cloudfoundry.MyClass$$Lambda$14.0x0000000800c02a00");
+    }
+
     #[test]
     fn double() {
         assert_eq!(ClassFormatter::new().format(&String::from("D")), "double");
@@ -14,43 +14,49 @@
  * limitations under the License.
  */
 
-use std::{mem, ptr};
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::os::raw::c_void;
+use std::ptr;
 
 use crate::bindings::{jint, jlong, JVMTI_VISIT_OBJECTS, jvmtiHeapCallbacks, jvmtiHeapReferenceInfo, jvmtiHeapReferenceKind};
+use crate::heap::dominators::{Dominators, Graph, ROOT};
 use crate::jvmti::JVMTI;
 
 pub struct Contents<'c, J: JVMTI> {
     jvmti: &'c J,
     contents: Vec<Statistics>,
+    retained_classes: Vec<Retained>,
+    retained_objects: Vec<Retained>,
+    retained_sizes: bool,
 }
 
 impl<'c, J: JVMTI> Contents<'c, J> {
-    pub fn new(jvmti: &'c J) -> Self {
-        return Self { jvmti, contents: Vec::new() };
+    pub fn new(jvmti: &'c J, retained_sizes: bool) -> Self {
+        return Self { jvmti, contents: Vec::new(), retained_classes: Vec::new(), retained_objects: Vec::new(), retained_sizes };
     }
 
-    pub fn analyze_heap(&mut self) {
-        let mut contents: HashMap<jlong, Statistics> = HashMap::new();
+    pub fn analyze_heap(&mut self, reset_tags: bool) {
+        if reset_tags {
+            self.reset_tags();
+        }
 
-        let mut c = |tag, size| {
-            let s = contents.entry(tag).or_insert(Statistics { tag, ..Default::default() });
-            s.count += 1;
-            s.total_size += size;
-        };
-
-        let mut p: &mut dyn FnMut(jlong, jlong) = &mut c;
-        let pp: *const c_void = unsafe { mem::transmute(&mut p) };
+        let mut v = Visitor { contents: HashMap::new(), graph: if self.retained_sizes { Some(Graph::new()) } else { None } };
+        let pv: *const c_void = &mut v as *mut Visitor as *const c_void;
 
         let callbacks = jvmtiHeapCallbacks { heap_reference_callback: Some(heapReferenceCallback), ..Default::default() };
-        self.jvmti.follow_references(0, ptr::null_mut(), ptr::null_mut(), &callbacks, pp);
+        self.jvmti.follow_references(0, ptr::null_mut(), ptr::null_mut(), &callbacks, pv);
 
-        self.contents = contents.values()
+        self.contents = v.contents.values()
             .cloned()
             .collect();
 
         self.contents.sort_unstable_by(|s1, s2| s2.total_size.cmp(&s1.total_size));
+
+        match &v.graph {
+            Some(g) => self.analyze_retained_sizes(g, &v.contents),
+            None => {}
+        }
     }
 
     pub fn get_contents(&self, limit: usize) -> Vec<Statistics> {
@@ -58,6 +64,48 @@ impl<'c, J: JVMTI> Contents<'c, J> {
         c.truncate(limit);
         return c;
     }
+
+    pub fn get_retained_classes(&self, limit: usize) -> Vec<Retained> {
+        let mut c = self.retained_classes.to_vec();
+        c.truncate(limit);
+        return c;
+    }
+
+    pub fn get_retained_objects(&self, limit: usize) -> Vec<Retained> {
+        let mut c = self.retained_objects.to_vec();
+        c.truncate(limit);
+        return c;
+    }
+
+    fn analyze_retained_sizes(&mut self, graph: &Graph, contents: &HashMap<jlong, Statistics>) {
+        let d = Dominators::new(graph);
+
+        self.retained_classes = d.retained_by_class(graph).iter()
+            .map(|(&tag, &retained_size)| Retained { retained_size, size: contents[&tag].total_size, tag })
+            .collect();
+
+        self.retained_classes.sort_unstable_by_key(|r| Reverse(r.retained_size));
+
+        self.retained_objects = d.roots().iter()
+            .map(|&node| Retained { retained_size: d.retained_size(node), size: graph.size(node), tag: graph.class(node) })
+            .collect();
+
+        self.retained_objects.sort_unstable_by_key(|r| Reverse(r.retained_size));
+    }
+
+    // Visited masks and graph nodes left by an earlier heap walk, for example when a dry run analyzes the heap again,
+    // would otherwise hide objects from this walk. Class tags are kept since classes are tagged before the walk.
+    fn reset_tags(&self) {
+        let callbacks = jvmtiHeapCallbacks { heap_reference_callback: Some(resetTagCallback), ..Default::default() };
+        self.jvmti.follow_references(0, ptr::null_mut(), ptr::null_mut(), &callbacks, ptr::null());
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Retained {
+    pub retained_size: jlong,
+    pub size: jlong,
+    pub tag: jlong,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -67,34 +115,68 @@ pub struct Statistics {
     pub tag: jlong,
 }
 
+struct Visitor {
+    contents: HashMap<jlong, Statistics>,
+    graph: Option<Graph>,
+}
+
+// Object tags hold the class tag in the low 31 bits, the visited mask in bit 31 and, when retained sizes are
+// being analyzed, the object's node in the reference graph in the high 32 bits.
+const TAG_CLASS_MASK: jlong = (1 << 31) - 1;
+
+const TAG_NODE_SHIFT: jlong = 32;
+
 const TAG_VISITED_MASK: jlong = 1 << 31;
 
 #[allow(non_snake_case)]
 unsafe extern "C" fn heapReferenceCallback(_reference_kind: jvmtiHeapReferenceKind, _reference_info: *const jvmtiHeapReferenceInfo, class_tag: jlong, _referrer_class_tag: jlong, size: jlong,
-                                           tag_ptr: *mut jlong, _referrer_tag_ptr: *mut jlong, _length: jint, user_data: *mut c_void) -> jint {
-    if *tag_ptr & TAG_VISITED_MASK == TAG_VISITED_MASK {
-        return 0;
-    }
+                                           tag_ptr: *mut jlong, referrer_tag_ptr: *mut jlong, _length: jint, user_data: *mut c_void) -> jint {
+    let v = &mut *(user_data as *mut Visitor);
 
-    *tag_ptr |= TAG_VISITED_MASK;
+    let visited = *tag_ptr & TAG_VISITED_MASK == TAG_VISITED_MASK;
 
-    let tag = class_tag & !TAG_VISITED_MASK;
+    if !visited {
+        *tag_ptr |= TAG_VISITED_MASK;
 
-    let c: &mut &mut dyn FnMut(jlong, jlong) = mem::transmute(user_data);
-    c(tag, size);
+        let tag = class_tag & TAG_CLASS_MASK;
+        let s = v.contents.entry(tag).or_insert(Statistics { tag, ..Default::default() });
+        s.count += 1;
+        s.total_size += size;
 
+        match &mut v.graph {
+            Some(g) => *tag_ptr |= (g.add_node(tag, size) as jlong) << TAG_NODE_SHIFT,
+            None => {}
+        }
+    }
+
+    match &mut v.graph {
+        Some(g) => {
+            let referrer = if referrer_tag_ptr == ptr::null_mut() { ROOT } else { (*referrer_tag_ptr >> TAG_NODE_SHIFT) as u32 };
+            g.add_edge(referrer, (*tag_ptr >> TAG_NODE_SHIFT) as u32);
+        }
+        None => {}
+    }
+
+    return if visited { 0 } else { JVMTI_VISIT_OBJECTS as jint };
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn resetTagCallback(_reference_kind: jvmtiHeapReferenceKind, _reference_info: *const jvmtiHeapReferenceInfo, _class_tag: jlong, _referrer_class_tag: jlong, _size: jlong,
+                                      tag_ptr: *mut jlong, _referrer_tag_ptr: *mut jlong, _length: jint, _user_data: *mut c_void) -> jint {
+    *tag_ptr &= TAG_CLASS_MASK;
     return JVMTI_VISIT_OBJECTS as jint;
 }
 
 #[cfg(test)]
 mod tests {
     use std::os::raw::c_void;
+    use std::ptr;
 
     use mockall::Sequence;
 
     use crate::bindings::{jint, jlong, jvmtiHeapCallbacks, jvmtiHeapReferenceInfo, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_CLASS};
     use crate::heap::Contents;
-    use crate::heap::contents::Statistics;
+    use crate::heap::contents::{Retained, Statistics};
     use crate::jvmti::MockJVMTI;
 
     #[test]
@@ -109,6 +191,21 @@ mod tests {
         let referrer_tag_ptr = jni_type!(jlong) as *mut jlong;
         let length = 100 as jint;
 
+        let reset_tag = jni_type!(jlong) as *mut jlong;
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _, c: *const jvmtiHeapCallbacks, _| {
+                unsafe {
+                    *reset_tag = 5 | 1 << 31 | 7 << 32;
+                    (*c).heap_reference_callback.unwrap()(reference_kind, reference_info, 0, 0, 0, reset_tag, ptr::null_mut(), -1, ptr::null_mut());
+                    assert_eq!(*reset_tag, 5);
+                }
+
+                return ();
+            });
+
         jvmti
             .expect_follow_references()
             .times(1)
@@ -135,9 +232,65 @@ mod tests {
                 return ();
             });
 
-        let mut c = Contents::new(&jvmti);
-        c.analyze_heap();
+        let mut c = Contents::new(&jvmti, false);
+        c.analyze_heap(true);
 
         assert_eq!(c.get_contents(2), vec![Statistics { count: 2, total_size: 60, tag: 2 }, Statistics { count: 2, total_size: 40, tag: 1 }])
     }
+
+    #[test]
+    fn analyze_heap_and_get_retained() {
+        let mut jvmti = MockJVMTI::new();
+        let mut seq = Sequence::new();
+
+        let reference_kind = jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_CLASS;
+        let reference_info = jni_type_const!(jvmtiHeapReferenceInfo);
+        let referrer_class_tag = 100 as jlong;
+        let tags = jni_type!(3, jlong) as *mut jlong;
+        let length = 100 as jint;
+
+        let reset_tag = jni_type!(jlong) as *mut jlong;
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _, c: *const jvmtiHeapCallbacks, _| {
+                unsafe {
+                    *reset_tag = 5 | 1 << 31 | 7 << 32;
+                    (*c).heap_reference_callback.unwrap()(reference_kind, reference_info, 0, 0, 0, reset_tag, ptr::null_mut(), -1, ptr::null_mut());
+                    assert_eq!(*reset_tag, 5);
+                }
+
+                return ();
+            });
+
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _, c: *const jvmtiHeapCallbacks, u: *const c_void| {
+                unsafe {
+                    let h = (*c).heap_reference_callback
+                        .unwrap();
+
+                    for i in 0..3 {
+                        *tags.offset(i) = 0;
+                    }
+
+                    h(reference_kind, reference_info, 0, referrer_class_tag, 10, tags, ptr::null_mut(), length, u as *mut c_void);
+                    h(reference_kind, reference_info, 1, referrer_class_tag, 20, tags.offset(1), tags, length, u as *mut c_void);
+                    h(reference_kind, reference_info, 1, referrer_class_tag, 20, tags.offset(2), tags, length, u as *mut c_void);
+                    h(reference_kind, reference_info, 1, referrer_class_tag, 20, tags.offset(2), tags.offset(1), length, u as *mut c_void);
+                }
+
+                return ();
+            });
+
+        let mut c = Contents::new(&jvmti, true);
+        c.analyze_heap(true);
+
+        assert_eq!(c.get_contents(2), vec![Statistics { count: 2, total_size: 40, tag: 1 }, Statistics { count: 1, total_size: 10, tag: 0 }]);
+        assert_eq!(c.get_retained_classes(2), vec![Retained { retained_size: 50, size: 10, tag: 0 }, Retained { retained_size: 40, size: 40, tag: 1 }]);
+        assert_eq!(c.get_retained_objects(2), vec![Retained { retained_size: 50, size: 10, tag: 0 }]);
+    }
 }
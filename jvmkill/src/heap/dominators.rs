@@ -0,0 +1,328 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use crate::bindings::jlong;
+
+pub const ROOT: u32 = 0;
+
+const UNDEFINED: u32 = u32::MAX;
+
+// The reference graph recorded while following references. Node 0 is a synthetic root which refers to every heap root.
+pub struct Graph {
+    classes: Vec<jlong>,
+    edges: Vec<(u32, u32)>,
+    sizes: Vec<jlong>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        return Self { classes: vec![0], edges: Vec::new(), sizes: vec![0] };
+    }
+
+    pub fn add_edge(&mut self, from: u32, to: u32) {
+        self.edges.push((from, to));
+    }
+
+    pub fn add_node(&mut self, class: jlong, size: jlong) -> u32 {
+        self.classes.push(class);
+        self.sizes.push(size);
+        return (self.classes.len() - 1) as u32;
+    }
+
+    pub fn class(&self, node: u32) -> jlong {
+        return self.classes[node as usize];
+    }
+
+    pub fn len(&self) -> usize {
+        return self.classes.len();
+    }
+
+    pub fn size(&self, node: u32) -> jlong {
+        return self.sizes[node as usize];
+    }
+}
+
+// The dominator tree of a Graph, computed with the iterative algorithm of Cooper, Harvey and Kennedy
+// ("A Simple, Fast Dominance Algorithm"), along with the retained size of each node.
+pub struct Dominators {
+    idom: Vec<u32>,
+    postorder: Vec<u32>,
+    retained: Vec<jlong>,
+}
+
+impl Dominators {
+    pub fn new(graph: &Graph) -> Self {
+        let n = graph.len();
+        let successors = Adjacency::new(n, graph.edges.iter().cloned());
+        let predecessors = Adjacency::new(n, graph.edges.iter().map(|&(from, to)| (to, from)));
+
+        let postorder = Dominators::postorder(n, &successors);
+
+        let mut postorder_number = vec![UNDEFINED; n];
+        for (i, &node) in postorder.iter().enumerate() {
+            postorder_number[node as usize] = i as u32;
+        }
+
+        let mut idom = vec![UNDEFINED; n];
+        idom[ROOT as usize] = ROOT;
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &node in postorder.iter().rev().skip(1) {
+                let mut new_idom = UNDEFINED;
+
+                for &p in predecessors.get(node) {
+                    if idom[p as usize] == UNDEFINED {
+                        continue;
+                    }
+
+                    new_idom = if new_idom == UNDEFINED { p } else { Dominators::intersect(&idom, &postorder_number, p, new_idom) };
+                }
+
+                if idom[node as usize] != new_idom {
+                    idom[node as usize] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut retained = graph.sizes.clone();
+        for &node in &postorder {
+            if node != ROOT {
+                retained[idom[node as usize] as usize] += retained[node as usize];
+            }
+        }
+
+        return Self { idom, postorder, retained };
+    }
+
+    pub fn idom(&self, node: u32) -> u32 {
+        return self.idom[node as usize];
+    }
+
+    pub fn retained_by_class(&self, graph: &Graph) -> HashMap<jlong, jlong> {
+        let children = Adjacency::new(graph.len(), self.postorder.iter()
+            .filter(|&&node| node != ROOT)
+            .map(|&node| (self.idom(node), node)));
+
+        let mut active: HashMap<jlong, usize> = HashMap::new();
+        let mut totals: HashMap<jlong, jlong> = HashMap::new();
+
+        // An object only contributes to its class if none of its dominators has the same class, so that the objects in,
+        // for example, a linked list are not counted more than once.
+        let mut stack: Vec<(u32, usize)> = vec![(ROOT, 0)];
+        while !stack.is_empty() {
+            let top = stack.len() - 1;
+            let (node, i) = stack[top];
+
+            if i == 0 && node != ROOT {
+                let a = active.entry(graph.class(node)).or_insert(0);
+                if *a == 0 {
+                    *totals.entry(graph.class(node)).or_insert(0) += self.retained_size(node);
+                }
+                *a += 1;
+            }
+
+            let c = children.get(node);
+            if i < c.len() {
+                stack[top].1 += 1;
+                stack.push((c[i], 0));
+            } else {
+                stack.pop();
+                if node != ROOT {
+                    *active.get_mut(&graph.class(node)).expect("class not active") -= 1;
+                }
+            }
+        }
+
+        return totals;
+    }
+
+    pub fn retained_size(&self, node: u32) -> jlong {
+        return self.retained[node as usize];
+    }
+
+    pub fn roots(&self) -> Vec<u32> {
+        return self.postorder.iter()
+            .filter(|&&node| node != ROOT && self.idom(node) == ROOT)
+            .cloned()
+            .collect();
+    }
+
+    fn intersect(idom: &[u32], postorder_number: &[u32], a: u32, b: u32) -> u32 {
+        let mut a = a;
+        let mut b = b;
+
+        while a != b {
+            while postorder_number[a as usize] < postorder_number[b as usize] {
+                a = idom[a as usize];
+            }
+            while postorder_number[b as usize] < postorder_number[a as usize] {
+                b = idom[b as usize];
+            }
+        }
+
+        return a;
+    }
+
+    fn postorder(n: usize, successors: &Adjacency) -> Vec<u32> {
+        let mut postorder = Vec::with_capacity(n);
+        let mut visited = vec![false; n];
+
+        let mut stack: Vec<(u32, usize)> = vec![(ROOT, 0)];
+        visited[ROOT as usize] = true;
+
+        while !stack.is_empty() {
+            let top = stack.len() - 1;
+            let (node, i) = stack[top];
+
+            let s = successors.get(node);
+            if i < s.len() {
+                stack[top].1 += 1;
+
+                let next = s[i];
+                if !visited[next as usize] {
+                    visited[next as usize] = true;
+                    stack.push((next, 0));
+                }
+            } else {
+                stack.pop();
+                postorder.push(node);
+            }
+        }
+
+        return postorder;
+    }
+}
+
+struct Adjacency {
+    offsets: Vec<usize>,
+    targets: Vec<u32>,
+}
+
+impl Adjacency {
+    fn new<I: Iterator<Item=(u32, u32)> + Clone>(n: usize, edges: I) -> Self {
+        let mut offsets = vec![0; n + 1];
+        for (from, _) in edges.clone() {
+            offsets[from as usize + 1] += 1;
+        }
+        for i in 0..n {
+            offsets[i + 1] += offsets[i];
+        }
+
+        let mut next = offsets.clone();
+        let mut targets = vec![0; offsets[n]];
+        for (from, to) in edges {
+            targets[next[from as usize]] = to;
+            next[from as usize] += 1;
+        }
+
+        return Self { offsets, targets };
+    }
+
+    fn get(&self, node: u32) -> &[u32] {
+        return &self.targets[self.offsets[node as usize]..self.offsets[node as usize + 1]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::heap::dominators::{Dominators, Graph, ROOT};
+
+    #[test]
+    fn cycle() {
+        let mut g = Graph::new();
+        let a = g.add_node(1, 10);
+        let b = g.add_node(1, 10);
+        let c = g.add_node(2, 10);
+        g.add_edge(ROOT, a);
+        g.add_edge(a, b);
+        g.add_edge(b, a);
+        g.add_edge(b, c);
+
+        let d = Dominators::new(&g);
+
+        assert_eq!(d.idom(a), ROOT);
+        assert_eq!(d.idom(b), a);
+        assert_eq!(d.idom(c), b);
+        assert_eq!(d.retained_size(a), 30);
+        assert_eq!(d.roots(), vec![a]);
+    }
+
+    #[test]
+    fn diamond() {
+        let mut g = Graph::new();
+        let a = g.add_node(1, 10);
+        let b = g.add_node(2, 10);
+        let c = g.add_node(2, 10);
+        let d = g.add_node(1, 10);
+        let e = g.add_node(3, 10);
+        g.add_edge(ROOT, a);
+        g.add_edge(a, b);
+        g.add_edge(a, c);
+        g.add_edge(b, d);
+        g.add_edge(c, d);
+        g.add_edge(d, e);
+
+        let dominators = Dominators::new(&g);
+
+        assert_eq!(dominators.idom(a), ROOT);
+        assert_eq!(dominators.idom(b), a);
+        assert_eq!(dominators.idom(c), a);
+        assert_eq!(dominators.idom(d), a);
+        assert_eq!(dominators.idom(e), d);
+
+        assert_eq!(dominators.retained_size(ROOT), 50);
+        assert_eq!(dominators.retained_size(a), 50);
+        assert_eq!(dominators.retained_size(b), 10);
+        assert_eq!(dominators.retained_size(d), 20);
+        assert_eq!(dominators.retained_size(e), 10);
+
+        let mut expected = HashMap::new();
+        expected.insert(1, 50);
+        expected.insert(2, 20);
+        expected.insert(3, 10);
+        assert_eq!(dominators.retained_by_class(&g), expected);
+    }
+
+    #[test]
+    fn irreducible() {
+        let mut g = Graph::new();
+        let n: Vec<u32> = (0..5).map(|_| g.add_node(1, 1)).collect();
+        g.add_edge(ROOT, n[0]);
+        g.add_edge(ROOT, n[1]);
+        g.add_edge(n[0], n[2]);
+        g.add_edge(n[1], n[3]);
+        g.add_edge(n[1], n[4]);
+        g.add_edge(n[2], n[3]);
+        g.add_edge(n[3], n[2]);
+        g.add_edge(n[3], n[4]);
+        g.add_edge(n[4], n[3]);
+
+        let d = Dominators::new(&g);
+
+        for &node in &n {
+            assert_eq!(d.idom(node), ROOT);
+        }
+        assert_eq!(d.roots().len(), 5);
+    }
+}
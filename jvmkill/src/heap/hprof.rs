@@ -0,0 +1,988 @@
+/*
+ * Copyright 2015-2019 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bindings::{jclass, jint, jlong, JVMTI_VISIT_ABORT, JVMTI_VISIT_OBJECTS, jvalue, jvmtiHeapCallbacks, jvmtiHeapReferenceInfo, jvmtiHeapReferenceKind, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_ARRAY_ELEMENT, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_CLASS_LOADER, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_FIELD, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_GLOBAL, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_LOCAL, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_MONITOR, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STACK_LOCAL, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STATIC_FIELD, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_SYSTEM_CLASS, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_THREAD, jvmtiPrimitiveType};
+use crate::jni::JNI;
+use crate::jvmti::JVMTI;
+
+const ACC_STATIC: jint = 0x0008;
+
+const ID_SIZE: usize = 8;
+
+// Sub-records are buffered into heap dump segments of about this size
+const SEGMENT_SIZE: usize = 1 << 24;
+
+// Every record refers to a single, empty stack trace
+const STACK_TRACE_SERIAL: u32 = 1;
+
+// Strings are written before any object is reached, so their ids start above any id that an object could be tagged with
+const STRING_ID_BASE: u64 = 1 << 48;
+
+const RECORD_HEAP_DUMP_END: u8 = 0x2C;
+const RECORD_HEAP_DUMP_SEGMENT: u8 = 0x1C;
+const RECORD_LOAD_CLASS: u8 = 0x02;
+const RECORD_STACK_TRACE: u8 = 0x05;
+const RECORD_STRING: u8 = 0x01;
+
+const SUB_RECORD_CLASS_DUMP: u8 = 0x20;
+const SUB_RECORD_INSTANCE_DUMP: u8 = 0x21;
+const SUB_RECORD_OBJECT_ARRAY_DUMP: u8 = 0x22;
+const SUB_RECORD_PRIMITIVE_ARRAY_DUMP: u8 = 0x23;
+const SUB_RECORD_ROOT_JAVA_FRAME: u8 = 0x03;
+const SUB_RECORD_ROOT_JNI_GLOBAL: u8 = 0x01;
+const SUB_RECORD_ROOT_JNI_LOCAL: u8 = 0x02;
+const SUB_RECORD_ROOT_MONITOR_USED: u8 = 0x07;
+const SUB_RECORD_ROOT_STICKY_CLASS: u8 = 0x05;
+const SUB_RECORD_ROOT_THREAD_OBJECT: u8 = 0x08;
+const SUB_RECORD_ROOT_UNKNOWN: u8 = 0xFF;
+
+const TYPE_BOOLEAN: u8 = 4;
+const TYPE_BYTE: u8 = 8;
+const TYPE_CHAR: u8 = 5;
+const TYPE_DOUBLE: u8 = 7;
+const TYPE_FLOAT: u8 = 6;
+const TYPE_INT: u8 = 10;
+const TYPE_LONG: u8 = 11;
+const TYPE_OBJECT: u8 = 2;
+const TYPE_SHORT: u8 = 9;
+
+// Writes an HPROF heap dump of the objects reachable from the heap roots using only JVMTI, so that a dump can be taken
+// without the HotSpot Diagnostic MXBean. While the heap is walked, object tags hold HPROF object ids: loaded classes are
+// tagged 1 to n and every other object is tagged with the next free id when it is first reached. JVMTI reports the
+// fields of one object at a time, so each object is written as soon as the walk moves on to another, and only the class
+// and length of each object are kept until the walk ends.
+pub struct Hprof<'h, J: JVMTI, N: JNI> {
+    jni: &'h N,
+    jvmti: &'h J,
+}
+
+impl<'h, J: JVMTI, N: JNI> Hprof<'h, J, N> {
+    pub fn new(jvmti: &'h J, jni: &'h N) -> Self {
+        return Self { jni, jvmti };
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        self.clear_tags();
+
+        let mut v = Visitor::new(layout(&self.describe_classes()), &mut out);
+        v.start()?;
+
+        let pv: *const c_void = &mut v as *mut Visitor as *const c_void;
+
+        let callbacks = jvmtiHeapCallbacks {
+            array_primitive_value_callback: Some(arrayPrimitiveValueCallback),
+            heap_reference_callback: Some(heapReferenceCallback),
+            primitive_field_callback: Some(primitiveFieldCallback),
+            ..Default::default()
+        };
+        self.jvmti.follow_references(0, ptr::null_mut(), ptr::null_mut(), &callbacks, pv);
+
+        return v.finish();
+    }
+
+    // Tags left by an earlier heap walk would otherwise be mistaken for object ids
+    fn clear_tags(&self) {
+        let callbacks = jvmtiHeapCallbacks { heap_reference_callback: Some(clearTagCallback), ..Default::default() };
+        self.jvmti.follow_references(0, ptr::null_mut(), ptr::null_mut(), &callbacks, ptr::null());
+    }
+
+    fn describe_classes(&self) -> Vec<Description> {
+        let mut classes = Vec::new();
+
+        for c in self.jvmti.get_loaded_classes() {
+            classes.push(unsafe { *c });
+            self.jvmti.set_tag(c, classes.len() as jlong);
+        }
+
+        return classes.iter()
+            .map(|&c| {
+                let mut class = c;
+
+                let (signature, _) = self.jvmti.get_class_signature(&mut class);
+
+                let fields = self.jvmti.get_class_fields(&mut class).into_iter()
+                    .map(|f| {
+                        let (name, signature) = self.jvmti.get_field_name(&mut class, f);
+                        let is_static = self.jvmti.get_field_modifiers(&mut class, f) & ACC_STATIC != 0;
+                        return FieldDescription { is_static, name, signature };
+                    })
+                    .collect();
+
+                let interfaces = self.jvmti.get_implemented_interfaces(&mut class).into_iter()
+                    .filter_map(|i| self.index(i, classes.len()))
+                    .collect();
+
+                let superclass = self.jni.get_superclass(c)
+                    .and_then(|s| self.index(s, classes.len()));
+
+                return Description { fields, interfaces, signature, superclass };
+            })
+            .collect();
+    }
+
+    fn index(&self, class: jclass, count: usize) -> Option<usize> {
+        let mut c = class;
+        let tag = self.jvmti.get_tag(&mut c);
+        return if tag >= 1 && tag as usize <= count { Some(tag as usize - 1) } else { None };
+    }
+}
+
+// A loaded class as reported by JVMTI, with superclass and interfaces given as indexes of other loaded classes
+struct Description {
+    fields: Vec<FieldDescription>,
+    interfaces: Vec<usize>,
+    signature: String,
+    superclass: Option<usize>,
+}
+
+struct FieldDescription {
+    is_static: bool,
+    name: String,
+    signature: String,
+}
+
+// A class laid out for HPROF. Instance field data lists the class's own fields followed by those of each superclass.
+struct Class {
+    array: Option<u8>,
+    first_index: usize,
+    instance_fields: Vec<Field>,
+    instance_size: usize,
+    loader: u64,
+    name: String,
+    slots: Vec<Slot>,
+    statics: Vec<Static>,
+    superclass: u64,
+}
+
+impl Class {
+    // JVMTI field indexes count the fields of all implemented interfaces, then those of each class from
+    // java.lang.Object down to this class in GetClassFields order
+    fn slot(&self, index: jint) -> Option<Slot> {
+        return (index as usize).checked_sub(self.first_index)
+            .and_then(|i| self.slots.get(i))
+            .copied();
+    }
+}
+
+struct Field {
+    basic_type: u8,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Slot {
+    Instance(usize, u8),
+    Static(usize, usize),
+}
+
+struct Static {
+    basic_type: u8,
+    name: String,
+    value: u64,
+}
+
+// The object whose fields are currently being reported
+struct Object {
+    class: u64,
+    data: Vec<u8>,
+    id: u64,
+}
+
+// An object that has been reached, and whether it has been written
+struct Reached {
+    class: u64,
+    length: jint,
+    written: bool,
+}
+
+struct Visitor<'v> {
+    classes: Vec<Class>,
+    current: Option<Object>,
+    error: Option<io::Error>,
+    fallback: u64,
+    objects: Vec<Reached>,
+    output: Output<&'v mut dyn Write>,
+    strings: HashMap<String, u64>,
+    threads: HashMap<u64, u32>,
+}
+
+impl<'v> Visitor<'v> {
+    fn new(classes: Vec<Class>, out: &'v mut dyn Write) -> Self {
+        // Objects of classes that were loaded after the classes were described are written as plain java.lang.Objects
+        let fallback = classes.iter().position(|c| c.name == "java/lang/Object").map_or(0, |i| i as u64 + 1);
+
+        return Self { classes, current: None, error: None, fallback, objects: Vec::new(), output: Output::new(out), strings: HashMap::new(), threads: HashMap::new() };
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn class(&self, id: u64) -> Option<&Class> {
+        return (id as usize).checked_sub(1).and_then(|i| self.classes.get(i));
+    }
+
+    // A failed write aborts the heap walk
+    fn control(&self, control: u32) -> jint {
+        return if self.error.is_some() { JVMTI_VISIT_ABORT as jint } else { control as jint };
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // Objects without any reported references, fields or elements have not been written yet
+        for i in 0..self.objects.len() {
+            if !self.objects[i].written {
+                self.object((self.classes.len() + i + 1) as u64);
+            }
+        }
+
+        self.write_current();
+
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        for (i, c) in self.classes.iter().enumerate() {
+            let mut r = Record::new().u1(SUB_RECORD_CLASS_DUMP).id(i as u64 + 1).u4(STACK_TRACE_SERIAL).id(c.superclass).id(c.loader)
+                .id(0).id(0).id(0).id(0)
+                .u4(c.instance_size as u32)
+                .u2(0)
+                .u2(c.statics.len() as u16);
+
+            for s in &c.statics {
+                r = r.id(self.strings[s.name.as_str()]).u1(s.basic_type).value(s.basic_type, s.value);
+            }
+
+            r = r.u2(c.instance_fields.len() as u16);
+
+            for f in &c.instance_fields {
+                r = r.id(self.strings[f.name.as_str()]).u1(f.basic_type);
+            }
+
+            self.output.sub_record(&r.body, &[])?;
+        }
+
+        self.output.flush_segment()?;
+        self.output.record(RECORD_HEAP_DUMP_END, &[])?;
+        return self.output.out.flush();
+    }
+
+    // The references, fields and elements of an object are reported together, so the previous object is complete and
+    // is written once another is reported
+    fn object(&mut self, id: u64) -> Option<&mut Object> {
+        if self.current.as_ref().is_some_and(|o| o.id == id) {
+            return self.current.as_mut();
+        }
+
+        let i = (id as usize).checked_sub(self.classes.len() + 1).filter(|&i| self.objects.get(i).is_some_and(|r| !r.written))?;
+        self.write_current();
+
+        let r = &self.objects[i];
+        let data = match self.class(r.class) {
+            Some(c) => match c.array {
+                None => vec![0; c.instance_size],
+                Some(TYPE_OBJECT) => vec![0; r.length.max(0) as usize * ID_SIZE],
+                Some(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+
+        self.current = Some(Object { class: r.class, data, id });
+        self.objects[i].written = true;
+
+        return self.current.as_mut();
+    }
+
+    #[allow(non_upper_case_globals)]
+    fn root(&mut self, kind: jvmtiHeapReferenceKind, info: *const jvmtiHeapReferenceInfo, id: u64) {
+        let (thread, depth) = match kind {
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_LOCAL => unsafe { ((*info).jni_local.thread_tag, (*info).jni_local.depth) },
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STACK_LOCAL => unsafe { ((*info).stack_local.thread_tag, (*info).stack_local.depth) },
+            _ => (0, 0),
+        };
+
+        // A thread is reported before the locals on its stack
+        let thread = self.threads.get(&(thread as u64)).copied().unwrap_or(0);
+
+        let record = match kind {
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_GLOBAL => Record::new().u1(SUB_RECORD_ROOT_JNI_GLOBAL).id(id).id(0),
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_LOCAL => Record::new().u1(SUB_RECORD_ROOT_JNI_LOCAL).id(id).u4(thread).u4(depth as u32),
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_MONITOR => Record::new().u1(SUB_RECORD_ROOT_MONITOR_USED).id(id),
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STACK_LOCAL => Record::new().u1(SUB_RECORD_ROOT_JAVA_FRAME).id(id).u4(thread).u4(depth as u32),
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_SYSTEM_CLASS => Record::new().u1(SUB_RECORD_ROOT_STICKY_CLASS).id(id),
+            jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_THREAD => {
+                let serial = self.threads.len() as u32 + 1;
+                Record::new().u1(SUB_RECORD_ROOT_THREAD_OBJECT).id(id).u4(*self.threads.entry(id).or_insert(serial)).u4(STACK_TRACE_SERIAL)
+            }
+            _ => Record::new().u1(SUB_RECORD_ROOT_UNKNOWN).id(id),
+        };
+
+        let r = self.output.sub_record(&record.body, &[]);
+        self.check(r);
+    }
+
+    fn set_element(&mut self, id: u64, index: jint, value: u64) {
+        if let Some(o) = self.object(id) {
+            put(&mut o.data, index as usize * ID_SIZE, TYPE_OBJECT, value);
+        }
+    }
+
+    fn set_elements(&mut self, id: u64, elements: &[u8], size: usize) {
+        if let Some(o) = self.object(id) {
+            o.data = elements.chunks(size)
+                .flat_map(|e| if cfg!(target_endian = "little") { e.iter().rev().copied().collect::<Vec<u8>>() } else { e.to_vec() })
+                .collect();
+        }
+    }
+
+    fn set_field(&mut self, id: u64, index: jint, value: u64) {
+        let class = match self.object(id) {
+            Some(o) => o.class,
+            None => return,
+        };
+
+        if let Some(Slot::Instance(offset, basic_type)) = self.class(class).and_then(|c| c.slot(index)) {
+            if let Some(o) = self.object(id) {
+                put(&mut o.data, offset, basic_type, value);
+            }
+        }
+    }
+
+    fn set_loader(&mut self, id: u64, loader: u64) {
+        if let Some(i) = (id as usize).checked_sub(1).filter(|&i| i < self.classes.len()) {
+            self.classes[i].loader = loader;
+        }
+    }
+
+    fn set_static(&mut self, id: u64, index: jint, value: u64) {
+        if let Some(Slot::Static(class, i)) = self.class(id).and_then(|c| c.slot(index)) {
+            self.classes[class].statics[i].value = value;
+        }
+    }
+
+    // Writes the header and every record that precedes the heap dump
+    fn start(&mut self) -> io::Result<()> {
+        self.output.header()?;
+
+        for c in &self.classes {
+            let names = c.instance_fields.iter().map(|f| &f.name)
+                .chain(c.statics.iter().map(|s| &s.name))
+                .chain(Some(&c.name));
+
+            for name in names {
+                if !self.strings.contains_key(name.as_str()) {
+                    let id = STRING_ID_BASE + self.strings.len() as u64;
+                    self.output.record(RECORD_STRING, &Record::new().id(id).bytes(name.as_bytes()).body)?;
+                    self.strings.insert(name.clone(), id);
+                }
+            }
+        }
+
+        for (i, c) in self.classes.iter().enumerate() {
+            self.output.record(RECORD_LOAD_CLASS, &Record::new().u4(i as u32 + 1).id(i as u64 + 1).u4(STACK_TRACE_SERIAL).id(self.strings[c.name.as_str()]).body)?;
+        }
+
+        return self.output.record(RECORD_STACK_TRACE, &Record::new().u4(STACK_TRACE_SERIAL).u4(0).u4(0).body);
+    }
+
+    unsafe fn tag(&mut self, tag_ptr: *mut jlong, class_tag: jlong, length: jint) -> u64 {
+        if *tag_ptr != 0 {
+            return *tag_ptr as u64;
+        }
+
+        let class = if self.class(class_tag as u64).is_some() { class_tag as u64 } else { 0 };
+        self.objects.push(Reached { class, length, written: false });
+
+        let id = (self.classes.len() + self.objects.len()) as u64;
+        *tag_ptr = id as jlong;
+        return id;
+    }
+
+    fn write_current(&mut self) {
+        let object = match self.current.take() {
+            Some(o) => o,
+            None => return,
+        };
+
+        let r = match self.class(object.class).and_then(|c| c.array) {
+            None => {
+                let class = if object.class == 0 { self.fallback } else { object.class };
+                let r = Record::new().u1(SUB_RECORD_INSTANCE_DUMP).id(object.id).u4(STACK_TRACE_SERIAL).id(class).u4(object.data.len() as u32);
+                self.output.sub_record(&r.body, &object.data)
+            }
+            Some(basic_type) => {
+                let size = type_size(basic_type);
+                let count = (object.data.len() / size).min(u32::MAX as usize / size - ID_SIZE);
+
+                let r = if basic_type == TYPE_OBJECT {
+                    Record::new().u1(SUB_RECORD_OBJECT_ARRAY_DUMP).id(object.id).u4(STACK_TRACE_SERIAL).u4(count as u32).id(object.class)
+                } else {
+                    Record::new().u1(SUB_RECORD_PRIMITIVE_ARRAY_DUMP).id(object.id).u4(STACK_TRACE_SERIAL).u4(count as u32).u1(basic_type)
+                };
+
+                self.output.sub_record(&r.body, &object.data[..count * size])
+            }
+        };
+
+        self.check(r);
+    }
+}
+
+struct Output<W: Write> {
+    out: BufWriter<W>,
+    segment: Vec<u8>,
+}
+
+impl<W: Write> Output<W> {
+    fn new(out: W) -> Self {
+        return Self { out: BufWriter::new(out), segment: Vec::new() };
+    }
+
+    fn flush_segment(&mut self) -> io::Result<()> {
+        if self.segment.is_empty() {
+            return Ok(());
+        }
+
+        let s = mem::take(&mut self.segment);
+        self.record(RECORD_HEAP_DUMP_SEGMENT, &s)?;
+        self.segment = s;
+        self.segment.clear();
+
+        return Ok(());
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        return self.out.write_all(&Record::new().bytes(b"JAVA PROFILE 1.0.2\0").u4(ID_SIZE as u32).u8(now).body);
+    }
+
+    fn record(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        self.record_header(tag, body.len())?;
+        return self.out.write_all(body);
+    }
+
+    fn record_header(&mut self, tag: u8, length: usize) -> io::Result<()> {
+        return self.out.write_all(&Record::new().u1(tag).u4(0).u4(length as u32).body);
+    }
+
+    // Sub-records larger than a segment are written as a segment of their own rather than buffered
+    fn sub_record(&mut self, header: &[u8], body: &[u8]) -> io::Result<()> {
+        let length = header.len() + body.len();
+
+        if self.segment.len() + length > SEGMENT_SIZE {
+            self.flush_segment()?;
+        }
+
+        if length > SEGMENT_SIZE {
+            self.record_header(RECORD_HEAP_DUMP_SEGMENT, length)?;
+            self.out.write_all(header)?;
+            return self.out.write_all(body);
+        }
+
+        self.segment.extend_from_slice(header);
+        self.segment.extend_from_slice(body);
+        return Ok(());
+    }
+}
+
+struct Record {
+    body: Vec<u8>,
+}
+
+impl Record {
+    fn new() -> Self {
+        return Self { body: Vec::new() };
+    }
+
+    fn bytes(mut self, b: &[u8]) -> Self {
+        self.body.extend_from_slice(b);
+        return self;
+    }
+
+    fn id(self, id: u64) -> Self {
+        return self.u8(id);
+    }
+
+    fn u1(self, v: u8) -> Self {
+        return self.bytes(&[v]);
+    }
+
+    fn u2(self, v: u16) -> Self {
+        return self.bytes(&v.to_be_bytes());
+    }
+
+    fn u4(self, v: u32) -> Self {
+        return self.bytes(&v.to_be_bytes());
+    }
+
+    fn u8(self, v: u64) -> Self {
+        return self.bytes(&v.to_be_bytes());
+    }
+
+    fn value(self, basic_type: u8, value: u64) -> Self {
+        return self.bytes(&value.to_be_bytes()[ID_SIZE - type_size(basic_type)..]);
+    }
+}
+
+// Field and array element signatures, and JVMTI primitive types, use the characters of JNI type signatures
+fn basic_type(signature: u8) -> u8 {
+    return match signature {
+        b'B' => TYPE_BYTE,
+        b'C' => TYPE_CHAR,
+        b'D' => TYPE_DOUBLE,
+        b'F' => TYPE_FLOAT,
+        b'I' => TYPE_INT,
+        b'J' => TYPE_LONG,
+        b'S' => TYPE_SHORT,
+        b'Z' => TYPE_BOOLEAN,
+        _ => TYPE_OBJECT,
+    };
+}
+
+fn class_name(signature: &str) -> String {
+    return match signature.strip_prefix('L').and_then(|s| s.strip_suffix(';')) {
+        Some(n) => String::from(n),
+        None => String::from(signature),
+    };
+}
+
+fn instance_size(descriptions: &[Description], sizes: &mut Vec<Option<usize>>, i: usize) -> usize {
+    if let Some(s) = sizes[i] {
+        return s;
+    }
+
+    let own: usize = descriptions[i].fields.iter()
+        .filter(|f| !f.is_static)
+        .map(|f| type_size(basic_type(f.signature.as_bytes()[0])))
+        .sum();
+
+    let s = own + descriptions[i].superclass.map_or(0, |s| instance_size(descriptions, sizes, s));
+    sizes[i] = Some(s);
+    return s;
+}
+
+fn interfaces(descriptions: &[Description], all: &mut Vec<Option<BTreeSet<usize>>>, i: usize) -> BTreeSet<usize> {
+    if let Some(s) = &all[i] {
+        return s.clone();
+    }
+
+    let mut s = descriptions[i].superclass.map_or(BTreeSet::new(), |c| interfaces(descriptions, all, c));
+
+    for &j in &descriptions[i].interfaces {
+        s.insert(j);
+        s.extend(interfaces(descriptions, all, j));
+    }
+
+    all[i] = Some(s.clone());
+    return s;
+}
+
+fn layout(descriptions: &[Description]) -> Vec<Class> {
+    let mut sizes = vec![None; descriptions.len()];
+    let mut all = vec![None; descriptions.len()];
+
+    return (0..descriptions.len())
+        .map(|i| {
+            let d = &descriptions[i];
+            let size = instance_size(descriptions, &mut sizes, i);
+
+            let first_index = interfaces(descriptions, &mut all, i).iter()
+                .map(|&j| descriptions[j].fields.len())
+                .sum();
+
+            let mut chain = vec![i];
+            while let Some(s) = descriptions[chain[chain.len() - 1]].superclass {
+                chain.push(s);
+            }
+
+            let mut slots = Vec::new();
+            for &k in chain.iter().rev() {
+                let mut offset = size - instance_size(descriptions, &mut sizes, k);
+                let mut statics = 0;
+
+                for f in &descriptions[k].fields {
+                    let t = basic_type(f.signature.as_bytes()[0]);
+
+                    if f.is_static {
+                        slots.push(Slot::Static(k, statics));
+                        statics += 1;
+                    } else {
+                        slots.push(Slot::Instance(offset, t));
+                        offset += type_size(t);
+                    }
+                }
+            }
+
+            return Class {
+                array: d.signature.strip_prefix('[').map(|e| basic_type(e.as_bytes()[0])),
+                first_index,
+                instance_fields: d.fields.iter()
+                    .filter(|f| !f.is_static)
+                    .map(|f| Field { basic_type: basic_type(f.signature.as_bytes()[0]), name: f.name.clone() })
+                    .collect(),
+                instance_size: size,
+                loader: 0,
+                name: class_name(&d.signature),
+                slots,
+                statics: d.fields.iter()
+                    .filter(|f| f.is_static)
+                    .map(|f| Static { basic_type: basic_type(f.signature.as_bytes()[0]), name: f.name.clone(), value: 0 })
+                    .collect(),
+                superclass: d.superclass.map_or(0, |s| s as u64 + 1),
+            };
+        })
+        .collect();
+}
+
+fn put(data: &mut [u8], offset: usize, basic_type: u8, value: u64) {
+    let size = type_size(basic_type);
+
+    if offset + size <= data.len() {
+        data[offset..offset + size].copy_from_slice(&value.to_be_bytes()[ID_SIZE - size..]);
+    }
+}
+
+fn type_size(basic_type: u8) -> usize {
+    return match basic_type {
+        TYPE_BOOLEAN | TYPE_BYTE => 1,
+        TYPE_CHAR | TYPE_SHORT => 2,
+        TYPE_FLOAT | TYPE_INT => 4,
+        _ => 8,
+    };
+}
+
+unsafe fn value(value: jvalue, primitive_type: jvmtiPrimitiveType) -> u64 {
+    return match primitive_type as u8 {
+        b'B' => value.b as u8 as u64,
+        b'C' => value.c as u64,
+        b'D' => value.d.to_bits(),
+        b'F' => value.f.to_bits() as u64,
+        b'I' => value.i as u32 as u64,
+        b'J' => value.j as u64,
+        b'S' => value.s as u16 as u64,
+        b'Z' => value.z as u64,
+        _ => 0,
+    };
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn arrayPrimitiveValueCallback(_class_tag: jlong, _size: jlong, tag_ptr: *mut jlong, element_count: jint, element_type: jvmtiPrimitiveType, elements: *const c_void,
+                                                 user_data: *mut c_void) -> jint {
+    let v = &mut *(user_data as *mut Visitor);
+
+    let size = type_size(basic_type(element_type as u8));
+    v.set_elements(*tag_ptr as u64, slice::from_raw_parts(elements as *const u8, element_count as usize * size), size);
+
+    return v.control(0);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn clearTagCallback(_reference_kind: jvmtiHeapReferenceKind, _reference_info: *const jvmtiHeapReferenceInfo, _class_tag: jlong, _referrer_class_tag: jlong, _size: jlong,
+                                      tag_ptr: *mut jlong, _referrer_tag_ptr: *mut jlong, _length: jint, _user_data: *mut c_void) -> jint {
+    *tag_ptr = 0;
+    return JVMTI_VISIT_OBJECTS as jint;
+}
+
+#[allow(non_snake_case, non_upper_case_globals)]
+unsafe extern "C" fn heapReferenceCallback(reference_kind: jvmtiHeapReferenceKind, reference_info: *const jvmtiHeapReferenceInfo, class_tag: jlong, _referrer_class_tag: jlong, _size: jlong,
+                                           tag_ptr: *mut jlong, referrer_tag_ptr: *mut jlong, length: jint, user_data: *mut c_void) -> jint {
+    let v = &mut *(user_data as *mut Visitor);
+
+    let id = v.tag(tag_ptr, class_tag, length);
+
+    if referrer_tag_ptr == ptr::null_mut() {
+        v.root(reference_kind, reference_info, id);
+        return v.control(JVMTI_VISIT_OBJECTS);
+    }
+
+    let referrer = *referrer_tag_ptr as u64;
+
+    match reference_kind {
+        jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_ARRAY_ELEMENT => v.set_element(referrer, (*reference_info).array.index, id),
+        jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_CLASS_LOADER => v.set_loader(referrer, id),
+        jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_FIELD => v.set_field(referrer, (*reference_info).field.index, id),
+        jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STATIC_FIELD => v.set_static(referrer, (*reference_info).field.index, id),
+        _ => {}
+    }
+
+    return v.control(JVMTI_VISIT_OBJECTS);
+}
+
+#[allow(non_snake_case, non_upper_case_globals)]
+unsafe extern "C" fn primitiveFieldCallback(kind: jvmtiHeapReferenceKind, info: *const jvmtiHeapReferenceInfo, _object_class_tag: jlong, object_tag_ptr: *mut jlong, value: jvalue,
+                                            value_type: jvmtiPrimitiveType, user_data: *mut c_void) -> jint {
+    let v = &mut *(user_data as *mut Visitor);
+
+    match kind {
+        jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_FIELD => v.set_field(*object_tag_ptr as u64, (*info).field.index, self::value(value, value_type)),
+        jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STATIC_FIELD => v.set_static(*object_tag_ptr as u64, (*info).field.index, self::value(value, value_type)),
+        _ => {}
+    }
+
+    return v.control(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+    use std::os::raw::c_void;
+    use std::ptr;
+
+    use mockall::Sequence;
+
+    use crate::bindings::{jclass, jfieldID, jint, jlong, jvalue, jvmtiHeapCallbacks, jvmtiHeapReferenceInfo, jvmtiHeapReferenceInfoField, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_FIELD, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_GLOBAL, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STATIC_FIELD, jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_SYSTEM_CLASS, jvmtiPrimitiveType_JVMTI_PRIMITIVE_TYPE_INT};
+    use crate::heap::Hprof;
+    use crate::heap::hprof::{Description, FieldDescription, layout, RECORD_HEAP_DUMP_END, RECORD_HEAP_DUMP_SEGMENT, RECORD_STRING, Record, Slot, TYPE_BOOLEAN, TYPE_INT, TYPE_LONG, TYPE_OBJECT, TYPE_SHORT};
+    use crate::jni::MockJNI;
+    use crate::jvmti::{ArrayPointerLoadedClassesIterator, MockJVMTI};
+
+    #[test]
+    fn layout_fields() {
+        let classes = layout(&[
+            describe("Ljava/lang/Object;", None, vec![], vec![]),
+            describe("Lalpha;", Some(0), vec![], vec![field("S", "I", true), field("a", "J", false)]),
+            describe("Lbravo;", Some(1), vec![], vec![field("b", "Z", false), field("c", "Ljava/lang/Object;", false)]),
+            describe("[Lbravo;", Some(0), vec![], vec![]),
+        ]);
+
+        assert_eq!(classes[1].instance_size, 8);
+
+        let b = &classes[2];
+        assert_eq!(b.name, "bravo");
+        assert_eq!(b.superclass, 2);
+        assert_eq!(b.instance_size, 17);
+        assert_eq!(b.slot(0), Some(Slot::Static(1, 0)));
+        assert_eq!(b.slot(1), Some(Slot::Instance(9, TYPE_LONG)));
+        assert_eq!(b.slot(2), Some(Slot::Instance(0, TYPE_BOOLEAN)));
+        assert_eq!(b.slot(3), Some(Slot::Instance(1, TYPE_OBJECT)));
+        assert_eq!(b.slot(4), None);
+        assert_eq!(b.array, None);
+
+        assert_eq!(classes[3].name, "[Lbravo;");
+        assert_eq!(classes[3].array, Some(TYPE_OBJECT));
+    }
+
+    #[test]
+    fn layout_interfaces() {
+        let classes = layout(&[
+            describe("Ljava/lang/Object;", None, vec![], vec![]),
+            describe("Lalpha;", None, vec![], vec![field("X", "I", true)]),
+            describe("Lbravo;", None, vec![1], vec![field("Y", "I", true)]),
+            describe("Lcharlie;", Some(0), vec![2], vec![field("Z", "I", true), field("c", "I", false)]),
+            describe("Ldelta;", Some(3), vec![], vec![field("d", "S", false)]),
+        ]);
+
+        assert_eq!(classes[2].slot(0), None);
+        assert_eq!(classes[2].slot(1), Some(Slot::Static(2, 0)));
+
+        assert_eq!(classes[3].slot(2), Some(Slot::Static(3, 0)));
+        assert_eq!(classes[3].slot(3), Some(Slot::Instance(0, TYPE_INT)));
+
+        assert_eq!(classes[4].slot(2), Some(Slot::Static(3, 0)));
+        assert_eq!(classes[4].slot(3), Some(Slot::Instance(2, TYPE_INT)));
+        assert_eq!(classes[4].slot(4), Some(Slot::Instance(0, TYPE_SHORT)));
+    }
+
+    #[test]
+    fn write() {
+        let mut jni = MockJNI::new();
+        let mut jvmti = MockJVMTI::new();
+        let mut seq = Sequence::new();
+
+        let c_object = jni_type!(jclass);
+        let c_foo = jni_type!(jclass);
+        let c_int_array = jni_type!(jclass);
+
+        let classes = jni_type!(3, jclass) as *mut jclass;
+        unsafe {
+            *classes = c_object;
+            *classes.offset(1) = c_foo;
+            *classes.offset(2) = c_int_array;
+        }
+
+        let f_count = jni_type!(jfieldID);
+        let f_x = jni_type!(jfieldID);
+        let f_ref = jni_type!(jfieldID);
+
+        let tags = jni_type!(4, jlong) as *mut jlong;
+
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _, c: *const jvmtiHeapCallbacks, u: *const c_void| {
+                unsafe {
+                    assert!((*c).primitive_field_callback.is_none());
+
+                    *tags = 99;
+                    (*c).heap_reference_callback.unwrap()(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_GLOBAL, ptr::null(), 0, 0, 0, tags, ptr::null_mut(), -1, u as *mut c_void);
+                    assert_eq!(*tags, 0);
+                }
+
+                return ();
+            });
+
+        let loaded_classes = ArrayPointerLoadedClassesIterator { count: 3, classes };
+        jvmti
+            .expect_get_loaded_classes()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move || loaded_classes);
+
+        jvmti
+            .expect_set_tag()
+            .times(3)
+            .return_const(());
+
+        let tag = move |c: jclass| -> jlong {
+            return if ptr::eq(c, c_object) { 1 } else if ptr::eq(c, c_foo) { 2 } else if ptr::eq(c, c_int_array) { 3 } else { 0 };
+        };
+
+        jvmti
+            .expect_get_tag()
+            .returning_st(move |c| tag(unsafe { *c }));
+
+        jvmti
+            .expect_get_class_signature()
+            .returning_st(move |c| {
+                let s = ["Ljava/lang/Object;", "LFoo;", "[I"][tag(unsafe { *c }) as usize - 1];
+                return (String::from(s), String::new());
+            });
+
+        jni
+            .expect_get_superclass()
+            .returning_st(move |c| if ptr::eq(c, c_object) { None } else { Some(c_object) });
+
+        jvmti
+            .expect_get_implemented_interfaces()
+            .returning_st(|_| Vec::new());
+
+        jvmti
+            .expect_get_class_fields()
+            .returning_st(move |c| if ptr::eq(unsafe { *c }, c_foo) { vec![f_count, f_x, f_ref] } else { Vec::new() });
+
+        jvmti
+            .expect_get_field_name()
+            .returning_st(move |_, f| {
+                let (n, s) = if ptr::eq(f, f_count) { ("COUNT", "I") } else if ptr::eq(f, f_x) { ("x", "I") } else { ("ref", "Ljava/lang/Object;") };
+                return (String::from(n), String::from(s));
+            });
+
+        jvmti
+            .expect_get_field_modifiers()
+            .returning_st(move |_, f| if ptr::eq(f, f_count) { 0x0008 } else { 0x0001 });
+
+        jvmti
+            .expect_follow_references()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _, c: *const jvmtiHeapCallbacks, u: *const c_void| {
+                unsafe {
+                    let h = (*c).heap_reference_callback.unwrap();
+                    let p = (*c).primitive_field_callback.unwrap();
+                    let a = (*c).array_primitive_value_callback.unwrap();
+                    let u = u as *mut c_void;
+
+                    *tags = 2;
+                    h(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_SYSTEM_CLASS, ptr::null(), 0, 0, 0, tags, ptr::null_mut(), -1, u);
+                    p(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_STATIC_FIELD, &info(0), 0, tags, jvalue { i: 7 }, jvmtiPrimitiveType_JVMTI_PRIMITIVE_TYPE_INT, u);
+
+                    *tags.offset(1) = 0;
+                    h(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_GLOBAL, ptr::null(), 2, 0, 24, tags.offset(1), ptr::null_mut(), -1, u);
+                    p(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_FIELD, &info(1), 2, tags.offset(1), jvalue { i: 42 }, jvmtiPrimitiveType_JVMTI_PRIMITIVE_TYPE_INT, u);
+
+                    *tags.offset(2) = 0;
+                    h(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_FIELD, &info(2), 3, 2, 28, tags.offset(2), tags.offset(1), 3, u);
+                    a(3, 28, tags.offset(2), 3, jvmtiPrimitiveType_JVMTI_PRIMITIVE_TYPE_INT, [1 as jint, 2, 3].as_ptr() as *const c_void, u);
+
+                    *tags.offset(3) = 0;
+                    h(jvmtiHeapReferenceKind_JVMTI_HEAP_REFERENCE_JNI_GLOBAL, ptr::null(), 1, 0, 16, tags.offset(3), ptr::null_mut(), -1, u);
+                }
+
+                return ();
+            });
+
+        let mut out = Vec::new();
+        Hprof::new(&jvmti, &jni).write(&mut out).unwrap();
+
+        assert!(out.starts_with(b"JAVA PROFILE 1.0.2\0\0\0\0\x08"));
+
+        let (strings, segments) = parse(&out);
+
+        assert!(contains(&segments, &Record::new().u1(0x05).id(2).body));
+        assert!(contains(&segments, &Record::new().u1(0x01).id(4).id(0).body));
+
+        assert!(contains(&segments, &Record::new().u1(0x20).id(2).u4(1).id(1).id(0).id(0).id(0).id(0).id(0).u4(12).u2(0)
+            .u2(1).id(strings["COUNT"]).u1(TYPE_INT).u4(7)
+            .u2(2).id(strings["x"]).u1(TYPE_INT).id(strings["ref"]).u1(TYPE_OBJECT)
+            .body));
+
+        assert!(contains(&segments, &Record::new().u1(0x21).id(4).u4(1).id(2).u4(12).u4(42).id(5).body));
+        assert!(contains(&segments, &Record::new().u1(0x23).id(5).u4(1).u4(3).u1(TYPE_INT).u4(1).u4(2).u4(3).body));
+        assert!(contains(&segments, &Record::new().u1(0x21).id(6).u4(1).id(1).u4(0).body));
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        return haystack.windows(needle.len()).any(|w| w == needle);
+    }
+
+    fn describe(signature: &str, superclass: Option<usize>, interfaces: Vec<usize>, fields: Vec<FieldDescription>) -> Description {
+        return Description { fields, interfaces, signature: String::from(signature), superclass };
+    }
+
+    fn field(name: &str, signature: &str, is_static: bool) -> FieldDescription {
+        return FieldDescription { is_static, name: String::from(name), signature: String::from(signature) };
+    }
+
+    fn info(index: jint) -> jvmtiHeapReferenceInfo {
+        return jvmtiHeapReferenceInfo { field: jvmtiHeapReferenceInfoField { index } };
+    }
+
+    fn parse(b: &[u8]) -> (HashMap<String, u64>, Vec<u8>) {
+        let mut strings = HashMap::new();
+        let mut segments = Vec::new();
+
+        let mut i = b.iter().position(|&c| c == 0).unwrap() + 13;
+        let mut tag = 0;
+
+        while i < b.len() {
+            tag = b[i];
+            let length = u32::from_be_bytes(b[i + 5..i + 9].try_into().unwrap()) as usize;
+            let body = &b[i + 9..i + 9 + length];
+
+            match tag {
+                RECORD_STRING => { strings.insert(String::from_utf8(body[8..].to_vec()).unwrap(), u64::from_be_bytes(body[..8].try_into().unwrap())); }
+                RECORD_HEAP_DUMP_SEGMENT => segments.extend_from_slice(body),
+                _ => {}
+            }
+
+            i += 9 + length;
+        }
+
+        assert_eq!(tag, RECORD_HEAP_DUMP_END);
+        return (strings, segments);
+    }
+}
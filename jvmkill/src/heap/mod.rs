@@ -16,8 +16,11 @@
 
 pub use class_formatter::ClassFormatter;
 pub use contents::Contents;
+pub use hprof::Hprof;
 pub use types::Types;
 
 mod class_formatter;
 mod contents;
+mod dominators;
+mod hprof;
 mod types;
\ No newline at end of file
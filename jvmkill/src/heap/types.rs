@@ -14,29 +14,81 @@
  * limitations under the License.
  */
 
-use crate::bindings::jlong;
+use crate::bindings::{jclass, jlong, jmethodID};
+use crate::heap::ClassFormatter;
+use crate::jni::JNI;
 use crate::jvmti::JVMTI;
 
 pub struct Types<'t, J: JVMTI> {
+    classes: Vec<jclass>,
     jvmti: &'t J,
+    loaders: Vec<String>,
+    modules: Vec<String>,
     types: Vec<String>,
 }
 
 impl<'t, J: JVMTI> Types<'t, J> {
     pub fn new(jvmti: &'t J) -> Self {
-        return Self { jvmti, types: Vec::new() };
+        return Self { classes: Vec::new(), jvmti, loaders: Vec::new(), modules: Vec::new(), types: Vec::new() };
     }
 
     pub fn get(&self, tag: jlong) -> &String {
         return &self.types[tag as usize];
     }
 
+    pub fn get_loader(&self, tag: jlong) -> &String {
+        return &self.loaders[tag as usize];
+    }
+
+    pub fn get_module(&self, tag: jlong) -> &String {
+        return &self.modules[tag as usize];
+    }
+
+    pub fn resolve_loaders<N: JNI>(&mut self, jni: &N) {
+        let f = ClassFormatter::new();
+
+        for c in &self.classes {
+            let mut class = *c;
+
+            let loader = match self.jvmti.get_class_loader(&mut class) {
+                Some(l) => {
+                    let mut loader_class = jni.get_object_class(l);
+                    let (signature, _) = self.jvmti.get_class_signature(&mut loader_class);
+                    format!("{}@{:x}", f.format(&signature), self.jvmti.get_object_hash_code(l))
+                }
+                None => String::from("<bootstrap>"),
+            };
+
+            self.loaders.push(loader);
+        }
+    }
+
+    pub fn resolve_modules<N: JNI>(&mut self, jni: &N) {
+        // Looked up once, a missing Module.getName leaves every class in the unnamed module
+        let mut get_name: Option<Option<jmethodID>> = None;
+
+        for c in &self.classes {
+            let mut class = *c;
+
+            let name = self.jvmti.get_class_module(&mut class)
+                .and_then(|m| {
+                    let method = (*get_name.get_or_insert_with(|| jni.get_method(jni.get_object_class(m), "getName", "()Ljava/lang/String;")))?;
+
+                    return jni.call_object_method(m, method);
+                })
+                .and_then(|n| jni.get_string_utf_chars(n));
+
+            self.modules.push(name.unwrap_or(String::from("<unnamed>")));
+        }
+    }
+
     pub fn tag_classes(&mut self) {
         for c in self.jvmti.get_loaded_classes() {
             self.jvmti.set_tag(c, self.types.len() as jlong);
 
             let (signature, _) = self.jvmti.get_class_signature(c);
             self.types.push(signature);
+            self.classes.push(unsafe { *c });
         }
     }
 }
@@ -47,8 +99,9 @@ mod tests {
 
     use mockall::Sequence;
 
-    use crate::bindings::jclass;
+    use crate::bindings::{jclass, jmethodID, jobject, jstring};
     use crate::heap::Types;
+    use crate::jni::MockJNI;
     use crate::jvmti::{ArrayPointerLoadedClassesIterator, MockJVMTI};
 
     #[test]
@@ -122,4 +175,213 @@ mod tests {
         assert_eq!(t.get(1), "bravo-type");
         assert_eq!(t.get(2), "charlie-type");
     }
+
+    #[test]
+    fn resolve_loaders_and_get_loader() {
+        let mut jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let (_classes, c_alpha, c_bravo) = loaded_classes(&mut jvmti, &mut seq);
+
+        jvmti
+            .expect_get_class_loader()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_alpha))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(|_| None);
+
+        let i_loader = jni_type!(jobject);
+        jvmti
+            .expect_get_class_loader()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Some(i_loader));
+
+        let c_loader = jni_type!(jclass);
+        jni
+            .expect_get_object_class()
+            .withf_st(move |&a_instance| ptr::eq(a_instance, i_loader))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| c_loader);
+
+        jvmti
+            .expect_get_class_signature()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_loader))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const((String::from("Lorg/cloudfoundry/Loader;"), String::new()));
+
+        jvmti
+            .expect_get_object_hash_code()
+            .withf_st(move |&a_object| ptr::eq(a_object, i_loader))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(0x1b2c);
+
+        let mut t = Types::new(&mut jvmti);
+        t.tag_classes();
+        t.resolve_loaders(&jni);
+
+        assert_eq!(t.get_loader(0), "<bootstrap>");
+        assert_eq!(t.get_loader(1), "org.cloudfoundry.Loader@1b2c");
+    }
+
+    #[test]
+    fn resolve_modules_and_get_module() {
+        let mut jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let (_classes, c_alpha, c_bravo) = loaded_classes(&mut jvmti, &mut seq);
+
+        let i_module = jni_type!(jobject);
+        jvmti
+            .expect_get_class_module()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_alpha))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Some(i_module));
+
+        let c_module = jni_type!(jclass);
+        jni
+            .expect_get_object_class()
+            .withf_st(move |&a_instance| ptr::eq(a_instance, i_module))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| c_module);
+
+        let m_get_name = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_module)
+                    && a_method == "getName"
+                    && a_signature == "()Ljava/lang/String;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Some(m_get_name));
+
+        let s_name = jni_type!(jstring);
+        jni
+            .expect_call_object_method()
+            .withf_st(move |&a_instance, &a_method| ptr::eq(a_instance, i_module) && ptr::eq(a_method, m_get_name))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| Some(s_name));
+
+        jni
+            .expect_get_string_utf_chars()
+            .withf_st(move |&a_s| ptr::eq(a_s, s_name))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Some(String::from("java.base")));
+
+        let i_unnamed = jni_type!(jobject);
+        jvmti
+            .expect_get_class_module()
+            .withf_st(move |&a_class| ptr::eq(unsafe { *a_class }, c_bravo))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Some(i_unnamed));
+
+        jni
+            .expect_call_object_method()
+            .withf_st(move |&a_instance, &a_method| ptr::eq(a_instance, i_unnamed) && ptr::eq(a_method, m_get_name))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(|_, _| None);
+
+        let mut t = Types::new(&mut jvmti);
+        t.tag_classes();
+        t.resolve_modules(&jni);
+
+        assert_eq!(t.get_module(0), "java.base");
+        assert_eq!(t.get_module(1), "<unnamed>");
+    }
+
+    #[test]
+    fn resolve_modules_without_get_name() {
+        let mut jvmti = MockJVMTI::new();
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        loaded_classes(&mut jvmti, &mut seq);
+
+        let i_module = jni_type!(jobject);
+        jvmti
+            .expect_get_class_module()
+            .times(2)
+            .returning_st(move |_| Some(i_module));
+
+        let c_module = jni_type!(jclass);
+        jni
+            .expect_get_object_class()
+            .withf_st(move |&a_instance| ptr::eq(a_instance, i_module))
+            .times(1)
+            .return_once_st(move |_| c_module);
+
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, _| ptr::eq(a_class, c_module) && a_method == "getName")
+            .times(1)
+            .return_once_st(|_, _, _| None);
+
+        let mut t = Types::new(&mut jvmti);
+        t.tag_classes();
+        t.resolve_modules(&jni);
+
+        assert_eq!(t.get_module(0), "<unnamed>");
+        assert_eq!(t.get_module(1), "<unnamed>");
+    }
+
+    fn loaded_classes(jvmti: &mut MockJVMTI, seq: &mut Sequence) -> (*mut jclass, jclass, jclass) {
+        let classes = jni_type!(2, jclass) as *mut jclass;
+        let c_alpha = jni_type!(jclass);
+        let c_bravo = jni_type!(jclass);
+        unsafe {
+            *classes = c_alpha;
+            *classes.offset(1) = c_bravo;
+        }
+
+        let loaded_classes = ArrayPointerLoadedClassesIterator { count: 2, classes };
+        jvmti
+            .expect_get_loaded_classes()
+            .times(1)
+            .in_sequence(seq)
+            .return_once_st(move || loaded_classes);
+
+        jvmti
+            .expect_set_tag()
+            .withf_st(move |&a_class, &a_tag| ptr::eq(a_class, classes) && a_tag == 0)
+            .times(1)
+            .in_sequence(seq)
+            .return_const(());
+
+        jvmti
+            .expect_get_class_signature()
+            .withf_st(move |&a_class| ptr::eq(a_class, classes))
+            .times(1)
+            .in_sequence(seq)
+            .return_const((String::from("alpha-type"), String::from("alpha-generic")));
+
+        jvmti
+            .expect_set_tag()
+            .withf_st(move |&a_class, &a_tag| ptr::eq(a_class, unsafe { classes.offset(1) }) && a_tag == 1)
+            .times(1)
+            .in_sequence(seq)
+            .return_const(());
+
+        jvmti
+            .expect_get_class_signature()
+            .withf_st(move |&a_class| ptr::eq(a_class, unsafe { classes.offset(1) }))
+            .times(1)
+            .in_sequence(seq)
+            .return_const((String::from("bravo-type"), String::from("bravo-generic")));
+
+        return (classes, c_alpha, c_bravo);
+    }
 }
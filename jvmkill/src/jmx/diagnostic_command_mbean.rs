@@ -0,0 +1,209 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::bindings::{jclass, jobject, jvalue};
+use crate::jni::JNI;
+
+// The com.sun.management:type=DiagnosticCommand MBean, invoked through the platform MBeanServer. Each operation takes
+// the arguments of the corresponding jcmd diagnostic command and returns its output.
+pub struct DiagnosticCommandMBean<'d, J: JNI> {
+    class: jclass,
+    instance: jobject,
+    jni: &'d J,
+    name: jobject,
+}
+
+impl<'d, J: JNI> DiagnosticCommandMBean<'d, J> {
+    pub fn new(class: jclass, instance: jobject, name: jobject, jni: &'d J) -> Self {
+        return Self { class, instance, jni, name };
+    }
+
+    pub fn gc_heap_dump(&self, arguments: &[&str]) -> Option<String> {
+        return self.invoke("gcHeapDump", arguments);
+    }
+
+    fn invoke(&self, operation: &str, arguments: &[&str]) -> Option<String> {
+        let method = self.jni.get_method(self.class, "invoke", "(Ljavax/management/ObjectName;Ljava/lang/String;[Ljava/lang/Object;[Ljava/lang/String;)Ljava/lang/Object;")
+            .expect("MBeanServer.invoke not found");
+
+        let object = self.jni.find_class("java/lang/Object")
+            .expect("Object not found");
+
+        let string = self.jni.find_class("java/lang/String")
+            .expect("String not found");
+
+        let a = self.jni.new_object_array(arguments.len() as i32, string);
+        for (i, argument) in arguments.iter().enumerate() {
+            self.jni.set_object_array_element(a, i as i32, self.jni.new_string_utf(argument));
+        }
+
+        let params = self.jni.new_object_array(1, object);
+        self.jni.set_object_array_element(params, 0, a);
+
+        let signature = self.jni.new_object_array(1, string);
+        self.jni.set_object_array_element(signature, 0, self.jni.new_string_utf("[Ljava.lang.String;"));
+
+        let o = self.jni.new_string_utf(operation);
+
+        return self.jni.call_object_method_a(self.instance, method, &[jvalue { l: self.name }, jvalue { l: o }, jvalue { l: params }, jvalue { l: signature }])
+            .and_then(|r| self.jni.get_string_utf_chars(r));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use mockall::Sequence;
+
+    use crate::bindings::{jclass, jmethodID, jobject, jobjectArray, jstring};
+    use crate::jmx::diagnostic_command_mbean::DiagnosticCommandMBean;
+    use crate::jni::MockJNI;
+
+    #[test]
+    fn gc_heap_dump() {
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_mbean_server = jni_type!(jclass);
+        let i_mbean_server = jni_type!(jobject);
+        let i_name = jni_type!(jobject);
+
+        let m_invoke = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_mbean_server)
+                    && a_method == "invoke"
+                    && a_signature == "(Ljavax/management/ObjectName;Ljava/lang/String;[Ljava/lang/Object;[Ljava/lang/String;)Ljava/lang/Object;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_invoke));
+
+        let c_object = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/Object")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_object));
+
+        let c_string = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/String")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_string));
+
+        let a_arguments = jni_type!(jobjectArray);
+        jni
+            .expect_new_object_array()
+            .withf_st(move |&a_length, &a_class| a_length == 2 && ptr::eq(a_class, c_string))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| a_arguments);
+
+        for (i, argument) in ["-gz=1", "test-path"].iter().enumerate() {
+            let s_argument = jni_type!(jstring);
+            jni
+                .expect_new_string_utf()
+                .withf_st(move |a_s| a_s == *argument)
+                .times(1)
+                .in_sequence(&mut seq)
+                .return_once_st(move |_| s_argument);
+
+            jni
+                .expect_set_object_array_element()
+                .withf_st(move |&a_array, &a_index, &a_value| ptr::eq(a_array, a_arguments) && a_index == i as i32 && ptr::eq(a_value, s_argument))
+                .times(1)
+                .in_sequence(&mut seq)
+                .return_const(());
+        }
+
+        let a_params = jni_type!(jobjectArray);
+        jni
+            .expect_new_object_array()
+            .withf_st(move |&a_length, &a_class| a_length == 1 && ptr::eq(a_class, c_object))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| a_params);
+
+        jni
+            .expect_set_object_array_element()
+            .withf_st(move |&a_array, &a_index, &a_value| ptr::eq(a_array, a_params) && a_index == 0 && ptr::eq(a_value, a_arguments))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let a_signature = jni_type!(jobjectArray);
+        jni
+            .expect_new_object_array()
+            .withf_st(move |&a_length, &a_class| a_length == 1 && ptr::eq(a_class, c_string))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| a_signature);
+
+        let s_signature = jni_type!(jstring);
+        jni
+            .expect_new_string_utf()
+            .withf_st(move |a_s| a_s == "[Ljava.lang.String;")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| s_signature);
+
+        jni
+            .expect_set_object_array_element()
+            .withf_st(move |&a_array, &a_index, &a_value| ptr::eq(a_array, a_signature) && a_index == 0 && ptr::eq(a_value, s_signature))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let s_operation = jni_type!(jstring);
+        jni
+            .expect_new_string_utf()
+            .withf_st(move |a_s| a_s == "gcHeapDump")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| s_operation);
+
+        let s_output = jni_type!(jstring);
+        jni
+            .expect_call_object_method_a()
+            .withf_st(move |&a_instance, &a_method, a_args| {
+                ptr::eq(a_instance, i_mbean_server)
+                    && ptr::eq(a_method, m_invoke)
+                    && ptr::eq(unsafe { a_args[0].l }, i_name)
+                    && ptr::eq(unsafe { a_args[1].l }, s_operation)
+                    && ptr::eq(unsafe { a_args[2].l }, a_params)
+                    && ptr::eq(unsafe { a_args[3].l }, a_signature)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(s_output));
+
+        jni
+            .expect_get_string_utf_chars()
+            .withf_st(move |&a_s| ptr::eq(a_s, s_output))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Some(String::from("Heap dump file created")));
+
+        let d = DiagnosticCommandMBean::new(c_mbean_server, i_mbean_server, i_name, &jni);
+        assert_eq!(d.gc_heap_dump(&["-gz=1", "test-path"]), Some(String::from("Heap dump file created")));
+    }
+}
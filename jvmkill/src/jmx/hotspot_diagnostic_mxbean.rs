@@ -14,7 +14,7 @@
  * limitations under the License.
  */
 
-use crate::bindings::{jclass, JNI_TRUE, jobject, jvalue};
+use crate::bindings::{jclass, jobject, jvalue};
 use crate::jni::JNI;
 
 pub struct HotspotDiagnosticMXBean<'h, J: JNI> {
@@ -28,13 +28,13 @@ impl<'h, J: JNI> HotspotDiagnosticMXBean<'h, J> {
         return Self { class, instance, jni };
     }
 
-    pub fn dump_heap(&self, path: &str) {
+    pub fn dump_heap(&self, path: &str, live: bool) {
         let method = self.jni.get_method(self.class, "dumpHeap", "(Ljava/lang/String;Z)V")
             .expect("HotSpotDiagnosticMXBean.dumpHeap not found");
 
         let p = self.jni.new_string_utf(path);
 
-        self.jni.call_object_method_a(self.instance, method, &[jvalue { l: p }, jvalue { z: JNI_TRUE as u8 }])
+        self.jni.call_object_method_a(self.instance, method, &[jvalue { l: p }, jvalue { z: live as u8 }])
             .expect("unable to dump heap");
     }
 }
@@ -45,7 +45,7 @@ mod tests {
 
     use mockall::Sequence;
 
-    use crate::bindings::{jclass, jmethodID, JNI_TRUE, jobject, jstring};
+    use crate::bindings::{jclass, jmethodID, JNI_FALSE, JNI_TRUE, jobject, jstring};
     use crate::jmx::HotspotDiagnosticMXBean;
     use crate::jni::MockJNI;
 
@@ -92,6 +92,52 @@ mod tests {
             .return_once_st(move |_, _, _| Option::Some(e));
 
         let h = HotspotDiagnosticMXBean::new(c_hot_spot_diagnostic_mxbean, i_hot_spot_diagnostic_mxbean, &jni);
-        h.dump_heap("test-path");
+        h.dump_heap("test-path", true);
+    }
+
+    #[test]
+    fn dump_heap_all() {
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_hot_spot_diagnostic_mxbean = jni_type!(jclass);
+        let i_hot_spot_diagnostic_mxbean = jni_type!(jclass);
+
+        let m_dump_heap = jni_type!(jmethodID);
+        jni
+
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_hot_spot_diagnostic_mxbean)
+                    && a_method == "dumpHeap"
+                    && a_signature == "(Ljava/lang/String;Z)V"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_dump_heap));
+
+        let s_p = jni_type!(jstring);
+        jni
+            .expect_new_string_utf()
+            .withf_st(move |a_s| a_s == "test-path")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| s_p);
+
+        let e = jni_type!(jobject);
+        jni
+            .expect_call_object_method_a()
+            .withf_st(move |&a_instance, &a_method, a_args| {
+                ptr::eq(a_instance, i_hot_spot_diagnostic_mxbean)
+                    && ptr::eq(a_method, m_dump_heap)
+                    && ptr::eq(unsafe { a_args[0].l }, s_p)
+                    && unsafe { a_args[1].z } == JNI_FALSE as u8
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(e));
+
+        let h = HotspotDiagnosticMXBean::new(c_hot_spot_diagnostic_mxbean, i_hot_spot_diagnostic_mxbean, &jni);
+        h.dump_heap("test-path", false);
     }
 }
@@ -15,9 +15,11 @@
  */
 
 use crate::bindings::{jclass, jobject, jvalue};
+use crate::jmx::diagnostic_command_mbean::DiagnosticCommandMBean;
 use crate::jmx::hotspot_diagnostic_mxbean::HotspotDiagnosticMXBean;
 use crate::jmx::memory_mxbean::MemoryMXBean;
 use crate::jmx::memory_pool_mxbean::MemoryPoolMXBean;
+use crate::jmx::runtime_mxbean::RuntimeMXBean;
 use crate::jni::JNI;
 
 pub struct ManagementFactory<'m, J: JNI> {
@@ -33,6 +35,30 @@ impl<'m, J: JNI> ManagementFactory<'m, J> {
         return Self { class, jni };
     }
 
+    pub fn get_diagnostic_command_mbean(&self) -> DiagnosticCommandMBean<J> {
+        let method = self.jni.get_static_method(self.class, "getPlatformMBeanServer", "()Ljavax/management/MBeanServer;")
+            .expect("ManagementFactory.getPlatformMBeanServer not found");
+
+        let class = self.jni.find_class("javax/management/MBeanServer")
+            .expect("MBeanServer not found");
+
+        let instance = self.jni.call_static_object_method(self.class, method)
+            .expect("unable to get MBeanServer");
+
+        let c = self.jni.find_class("javax/management/ObjectName")
+            .expect("ObjectName not found");
+
+        let m = self.jni.get_static_method(c, "getInstance", "(Ljava/lang/String;)Ljavax/management/ObjectName;")
+            .expect("ObjectName.getInstance not found");
+
+        let s = self.jni.new_string_utf("com.sun.management:type=DiagnosticCommand");
+
+        let name = self.jni.call_static_object_method_a(c, m, &[jvalue { l: s }])
+            .expect("unable to get DiagnosticCommand ObjectName");
+
+        return DiagnosticCommandMBean::new(class, instance, name, &self.jni);
+    }
+
     pub fn get_hotspot_diagnostic_mxbean(&self) -> HotspotDiagnosticMXBean<J> {
         let method = self.jni.get_static_method(self.class, "getPlatformMXBean", "(Ljava/lang/Class;)Ljava/lang/management/PlatformManagedObject;")
             .expect("ManagementFactory.getPlatformMXBean not found");
@@ -69,6 +95,24 @@ impl<'m, J: JNI> ManagementFactory<'m, J> {
         return self.to_vec(instance);
     }
 
+    pub fn get_runtime_mxbean(&self) -> RuntimeMXBean<J> {
+        let method = self.jni.get_static_method(self.class, "getRuntimeMXBean", "()Ljava/lang/management/RuntimeMXBean;")
+            .expect("ManagementFactory.getRuntimeMXBean not found");
+
+        let class = self.jni.find_class("java/lang/management/RuntimeMXBean")
+            .expect("RuntimeMXBean not found");
+
+        let instance = self.jni.call_static_object_method(self.class, method)
+            .expect("unable to get RuntimeMXBean");
+
+        return RuntimeMXBean::new(class, instance, &self.jni);
+    }
+
+    // JVMs other than HotSpot and OpenJ9 may not provide the HotSpot Diagnostic MXBean
+    pub fn has_hotspot_diagnostic_mxbean(&self) -> bool {
+        return self.jni.find_class_optional("com/sun/management/HotSpotDiagnosticMXBean").is_some();
+    }
+
     fn size(&self, class: jclass, instance: jobject) -> i32 {
         let method = self.jni.get_method(class, "size", "()I")
             .expect("List.size not found");
@@ -105,10 +149,97 @@ mod tests {
 
     use mockall::Sequence;
 
-    use crate::bindings::{jclass, jint, jmethodID, jobject};
+    use crate::bindings::{jclass, jint, jmethodID, jobject, jstring};
     use crate::jmx::ManagementFactory;
     use crate::jni::MockJNI;
 
+    #[test]
+    fn get_diagnostic_command_mbean() {
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let m_get_platform_mbean_server = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_management_factory)
+                    && a_method == "getPlatformMBeanServer"
+                    && a_signature == "()Ljavax/management/MBeanServer;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_platform_mbean_server));
+
+        let c_mbean_server = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "javax/management/MBeanServer")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_mbean_server));
+
+        let i_mbean_server = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method()
+            .withf_st(move |&a_class, &a_method| {
+                ptr::eq(a_class, c_management_factory)
+                    && ptr::eq(a_method, m_get_platform_mbean_server)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| Option::Some(i_mbean_server));
+
+        let c_object_name = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "javax/management/ObjectName")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_object_name));
+
+        let m_get_instance = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_object_name)
+                    && a_method == "getInstance"
+                    && a_signature == "(Ljava/lang/String;)Ljavax/management/ObjectName;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_instance));
+
+        let s_name = jni_type!(jstring);
+        jni
+            .expect_new_string_utf()
+            .withf_st(move |a_s| a_s == "com.sun.management:type=DiagnosticCommand")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| s_name);
+
+        let i_object_name = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method_a()
+            .withf_st(move |&a_class, &a_method, a_args| {
+                ptr::eq(a_class, c_object_name)
+                    && ptr::eq(a_method, m_get_instance)
+                    && ptr::eq(unsafe { a_args[0].l }, s_name)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(i_object_name));
+
+        ManagementFactory::new(&jni).get_diagnostic_command_mbean();
+    }
+
     #[test]
     fn get_hotspot_diagnostic_mxbean() {
         let mut jni = MockJNI::new();
@@ -331,4 +462,84 @@ mod tests {
         let mp = m.get_memory_pool_mxbeans();
         assert_eq!(mp.len(), 3)
     }
+
+    #[test]
+    fn get_runtime_mxbean() {
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let m_get_runtime_mxbean = jni_type!(jmethodID);
+        jni
+            .expect_get_static_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_management_factory)
+                    && a_method == "getRuntimeMXBean"
+                    && a_signature == "()Ljava/lang/management/RuntimeMXBean;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_runtime_mxbean));
+
+        let c_runtime_mxbean = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/RuntimeMXBean")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_runtime_mxbean));
+
+        let i_runtime_mxbean = jni_type!(jobject);
+        jni
+            .expect_call_static_object_method()
+            .withf_st(move |&a_class, &a_method| {
+                ptr::eq(a_class, c_management_factory)
+                    && ptr::eq(a_method, m_get_runtime_mxbean)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| Option::Some(i_runtime_mxbean));
+
+        ManagementFactory::new(&jni).get_runtime_mxbean();
+    }
+
+    #[test]
+    fn has_hotspot_diagnostic_mxbean() {
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_management_factory = jni_type!(jclass);
+        jni
+            .expect_find_class()
+            .withf_st(move |a_class| a_class == "java/lang/management/ManagementFactory")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_management_factory));
+
+        let c_hotspot_diagnostic_mxbean = jni_type!(jclass);
+        jni
+            .expect_find_class_optional()
+            .withf_st(move |a_class| a_class == "com/sun/management/HotSpotDiagnosticMXBean")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::Some(c_hotspot_diagnostic_mxbean));
+
+        jni
+            .expect_find_class_optional()
+            .withf_st(move |a_class| a_class == "com/sun/management/HotSpotDiagnosticMXBean")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_| Option::None);
+
+        let m = ManagementFactory::new(&jni);
+        assert!(m.has_hotspot_diagnostic_mxbean());
+        assert!(!m.has_hotspot_diagnostic_mxbean());
+    }
 }
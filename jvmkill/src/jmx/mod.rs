@@ -20,9 +20,11 @@ pub use memory_mxbean::MemoryMXBean;
 pub use memory_pool_mxbean::MemoryPoolMXBean;
 pub use memory_usage::MemoryUsage;
 
+mod diagnostic_command_mbean;
 mod hotspot_diagnostic_mxbean;
 mod management_factory;
 mod memory_mxbean;
 mod memory_pool_mxbean;
 mod memory_usage;
+mod runtime_mxbean;
 
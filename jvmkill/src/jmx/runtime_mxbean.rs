@@ -0,0 +1,91 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::bindings::{jclass, jobject};
+use crate::jni::JNI;
+
+pub struct RuntimeMXBean<'r, J: JNI> {
+    class: jclass,
+    instance: jobject,
+    jni: &'r J,
+}
+
+impl<'r, J: JNI> RuntimeMXBean<'r, J> {
+    pub fn new(class: jclass, instance: jobject, jni: &'r J) -> Self {
+        return Self { class, instance, jni };
+    }
+
+    pub fn get_spec_version(&self) -> String {
+        let method = self.jni.get_method(self.class, "getSpecVersion", "()Ljava/lang/String;")
+            .expect("RuntimeMXBean.getSpecVersion not found");
+
+        let s = self.jni.call_object_method(self.instance, method)
+            .expect("unable to get spec version");
+
+        return self.jni.get_string_utf_chars(s)
+            .expect("unable to convert spec version");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use mockall::Sequence;
+
+    use crate::bindings::{jclass, jmethodID, jobject, jstring};
+    use crate::jmx::runtime_mxbean::RuntimeMXBean;
+    use crate::jni::MockJNI;
+
+    #[test]
+    fn get_spec_version() {
+        let mut jni = MockJNI::new();
+        let mut seq = Sequence::new();
+
+        let c_runtime_mxbean = jni_type!(jclass);
+        let i_runtime_mxbean = jni_type!(jobject);
+
+        let m_get_spec_version = jni_type!(jmethodID);
+        jni
+            .expect_get_method()
+            .withf_st(move |&a_class, a_method, a_signature| {
+                ptr::eq(a_class, c_runtime_mxbean)
+                    && a_method == "getSpecVersion"
+                    && a_signature == "()Ljava/lang/String;"
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _, _| Option::Some(m_get_spec_version));
+
+        let s_spec_version = jni_type!(jstring);
+        jni
+            .expect_call_object_method()
+            .withf_st(move |&a_instance, &a_method| ptr::eq(a_instance, i_runtime_mxbean) && ptr::eq(a_method, m_get_spec_version))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once_st(move |_, _| Option::Some(s_spec_version));
+
+        jni
+            .expect_get_string_utf_chars()
+            .withf_st(move |&a_s| ptr::eq(a_s, s_spec_version))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Some(String::from("11")));
+
+        let r = RuntimeMXBean::new(c_runtime_mxbean, i_runtime_mxbean, &jni);
+        assert_eq!(r.get_spec_version(), "11");
+    }
+}
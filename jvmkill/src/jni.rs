@@ -20,7 +20,7 @@ use std::ptr;
 
 use mockall::automock;
 
-use crate::bindings::{jclass, jint, jlong, jmethodID, JNI_TRUE, JNIEnv, jobject, jstring, jvalue};
+use crate::bindings::{jclass, jint, jlong, jmethodID, JNI_TRUE, JNIEnv, jobject, jobjectArray, jstring, jvalue};
 
 #[cfg_attr(test, automock)]
 pub trait JNI {
@@ -36,15 +36,27 @@ pub trait JNI {
 
     fn call_static_object_method_a(&self, class: jclass, method: jmethodID, args: &[jvalue]) -> Option<jobject>;
 
+    fn call_void_method_a(&self, instance: jobject, method: jmethodID, args: &[jvalue]);
+
     fn find_class(&self, class: &str) -> Option<jclass>;
 
+    fn find_class_optional(&self, class: &str) -> Option<jclass>;
+
     fn get_method(&self, class: jclass, method: &str, signature: &str) -> Option<jmethodID>;
 
+    fn get_object_class(&self, instance: jobject) -> jclass;
+
     fn get_static_method(&self, class: jclass, method: &str, signature: &str) -> Option<jmethodID>;
 
     fn get_string_utf_chars(&self, s: jstring) -> Option<String>;
 
+    fn get_superclass(&self, class: jclass) -> Option<jclass>;
+
+    fn new_object_array(&self, length: jint, class: jclass) -> jobjectArray;
+
     fn new_string_utf(&self, s: &str) -> jstring;
+
+    fn set_object_array_element(&self, array: jobjectArray, index: jint, value: jobject);
 }
 
 pub struct DefaultJNI {
@@ -63,6 +75,13 @@ impl DefaultJNI {
         return unsafe { f(self.internal) == JNI_TRUE as u8 };
     }
 
+    fn exception_clear(&self) {
+        let f = unsafe { (**self.internal).ExceptionClear }
+            .expect("JNIEnv.ExceptionClear not found");
+
+        unsafe { f(self.internal) };
+    }
+
     fn exception_describe(&self) {
         let f = unsafe { (**self.internal).ExceptionDescribe }
             .expect("JNIEnv.ExceptionDescribe not found");
@@ -165,6 +184,18 @@ impl JNI for DefaultJNI {
         }
     }
 
+    fn call_void_method_a(&self, instance: jobject, method: jmethodID, args: &[jvalue]) {
+        let f = unsafe { (**self.internal).CallVoidMethodA }
+            .expect("JNIEnv.CallVoidMethodA not found");
+
+        // The exception is cleared rather than panicking so that the caller can fall back to another approach
+        unsafe { f(self.internal, instance, method, &args[0]) };
+        if self.exception_check() {
+            self.exception_describe();
+            self.exception_clear();
+        }
+    }
+
     fn find_class(&self, class: &str) -> Option<jclass> {
         let c = CString::new(class)
             .expect("unable to create CString");
@@ -183,6 +214,25 @@ impl JNI for DefaultJNI {
         }
     }
 
+    fn find_class_optional(&self, class: &str) -> Option<jclass> {
+        let c = CString::new(class)
+            .expect("unable to create CString");
+
+        let f = unsafe { (**self.internal).FindClass }
+            .expect("JNIEnv.FindClass not found");
+
+        // A missing class raises NoClassDefFoundError, which is cleared since the caller expects that it may be missing
+        let r = unsafe { f(self.internal, c.as_ptr()) };
+        if self.exception_check() {
+            self.exception_clear();
+            return None;
+        } else if r == ptr::null_mut() {
+            return None;
+        } else {
+            return Some(r);
+        }
+    }
+
     fn get_method(&self, class: jclass, method: &str, signature: &str) -> Option<jmethodID> {
         let m = CString::new(method)
             .expect("unable to create CString");
@@ -200,6 +250,13 @@ impl JNI for DefaultJNI {
         }
     }
 
+    fn get_object_class(&self, instance: jobject) -> jclass {
+        let f = unsafe { (**self.internal).GetObjectClass }
+            .expect("JNIEnv.GetObjectClass not found");
+
+        return unsafe { f(self.internal, instance) };
+    }
+
     fn get_static_method(&self, class: jclass, method: &str, signature: &str) -> Option<jmethodID> {
         let m = CString::new(method)
             .expect("unable to create CString");
@@ -234,6 +291,31 @@ impl JNI for DefaultJNI {
         return Some(c);
     }
 
+    fn get_superclass(&self, class: jclass) -> Option<jclass> {
+        let f = unsafe { (**self.internal).GetSuperclass }
+            .expect("JNIEnv.GetSuperclass not found");
+
+        let r = unsafe { f(self.internal, class) };
+        if r == ptr::null_mut() {
+            return None;
+        }
+
+        return Some(r);
+    }
+
+    fn new_object_array(&self, length: jint, class: jclass) -> jobjectArray {
+        let f = unsafe { (**self.internal).NewObjectArray }
+            .expect("JNIEnv.NewObjectArray not found");
+
+        let r = unsafe { f(self.internal, length, class, ptr::null_mut()) };
+        if self.exception_check() {
+            self.exception_describe();
+            panic!();
+        } else {
+            return r;
+        }
+    }
+
     fn new_string_utf(&self, s: &str) -> jstring {
         let c = CString::new(s)
             .expect("unable to create CString");
@@ -243,4 +325,15 @@ impl JNI for DefaultJNI {
 
         return unsafe { f(self.internal, c.as_ptr()) };
     }
+
+    fn set_object_array_element(&self, array: jobjectArray, index: jint, value: jobject) {
+        let f = unsafe { (**self.internal).SetObjectArrayElement }
+            .expect("JNIEnv.SetObjectArrayElement not found");
+
+        unsafe { f(self.internal, array, index, value) };
+        if self.exception_check() {
+            self.exception_describe();
+            panic!();
+        }
+    }
 }
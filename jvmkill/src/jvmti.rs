@@ -16,12 +16,12 @@
 
 use std::ffi::CStr;
 use std::mem;
-use std::os::raw::{c_uchar, c_void};
-use std::ptr;
+use std::os::raw::{c_char, c_uchar, c_void};
+use std::{ptr, slice};
 
 use mockall::automock;
 
-use crate::bindings::{JavaVM, jclass, jint, jlong, JNI_OK, jthread, JVMTI_VERSION_1, JVMTI_VERSION_11, JVMTI_VERSION_1_0, JVMTI_VERSION_1_1, JVMTI_VERSION_1_2, JVMTI_VERSION_9, jvmtiCapabilities, jvmtiEnv, jvmtiError_JVMTI_ERROR_NONE, jvmtiEvent, jvmtiEventCallbacks, jvmtiEventMode, jvmtiHeapCallbacks};
+use crate::bindings::{JavaVM, jclass, jfieldID, jint, jlong, jmethodID, JNI_OK, jobject, jthread, JVMTI_VERSION_1, JVMTI_VERSION_11, JVMTI_VERSION_1_0, JVMTI_VERSION_1_1, JVMTI_VERSION_1_2, JVMTI_VERSION_9, jvmtiCapabilities, jvmtiEnv, jvmtiError, jvmtiError_JVMTI_ERROR_ABSENT_INFORMATION, jvmtiError_JVMTI_ERROR_CLASS_NOT_PREPARED, jvmtiError_JVMTI_ERROR_NATIVE_METHOD, jvmtiError_JVMTI_ERROR_NONE, jvmtiEvent, jvmtiEventCallbacks, jvmtiEventMode, jvmtiFrameInfo, jvmtiHeapCallbacks, jvmtiLineNumberEntry, jvmtiThreadInfo};
 
 #[cfg_attr(test, automock(type LoadedClassesIterator = ArrayPointerLoadedClassesIterator;))]
 pub trait JVMTI {
@@ -31,10 +31,42 @@ pub trait JVMTI {
 
     fn follow_references(&self, heap_filter: jint, class: jclass, initial_object: jclass, callbacks: *const jvmtiHeapCallbacks, user_data: *const c_void);
 
+    fn get_all_threads(&self) -> Vec<jthread>;
+
+    fn get_class_fields(&self, class: *mut jclass) -> Vec<jfieldID>;
+
+    fn get_class_loader(&self, class: *mut jclass) -> Option<jobject>;
+
+    fn get_class_module(&self, class: *mut jclass) -> Option<jobject>;
+
     fn get_class_signature(&self, class: *mut jclass) -> (String, String);
 
+    fn get_field_modifiers(&self, class: *mut jclass, field: jfieldID) -> jint;
+
+    fn get_field_name(&self, class: *mut jclass, field: jfieldID) -> (String, String);
+
+    fn get_implemented_interfaces(&self, class: *mut jclass) -> Vec<jclass>;
+
+    fn get_line_number_table(&self, method: jmethodID) -> Vec<jvmtiLineNumberEntry>;
+
     fn get_loaded_classes(&self) -> Self::LoadedClassesIterator;
 
+    fn get_method_declaring_class(&self, method: jmethodID) -> jclass;
+
+    fn get_method_name(&self, method: jmethodID) -> (String, String);
+
+    fn get_object_hash_code(&self, object: jobject) -> jint;
+
+    fn get_source_file_name(&self, class: jclass) -> Option<String>;
+
+    fn get_stack_trace(&self, thread: jthread, max_frame_count: jint) -> Result<Vec<jvmtiFrameInfo>, jvmtiError>;
+
+    fn get_tag(&self, object: *mut jobject) -> jlong;
+
+    fn get_thread_info(&self, thread: jthread) -> Result<ThreadInfo, jvmtiError>;
+
+    fn get_thread_state(&self, thread: jthread) -> Result<jint, jvmtiError>;
+
     fn set_event_callbacks(&self, callbacks: *const jvmtiEventCallbacks);
 
     fn set_event_notification_mode(&self, mode: jvmtiEventMode, event_type: jvmtiEvent, event_thread: jthread);
@@ -80,6 +112,15 @@ impl DefaultJVMTI {
 
         panic!("JVMTI not available");
     }
+
+    fn to_string(&self, s: *mut c_char) -> String {
+        let r = String::from(unsafe { CStr::from_ptr(s) }
+            .to_string_lossy());
+
+        self.deallocate(s as *mut c_uchar);
+
+        return r;
+    }
 }
 
 impl JVMTI for DefaultJVMTI {
@@ -105,6 +146,88 @@ impl JVMTI for DefaultJVMTI {
         }
     }
 
+    fn get_all_threads(&self) -> Vec<jthread> {
+        let f = unsafe { (**self.internal).GetAllThreads }
+            .expect("jvmtiEnv.GetAllThreads not found");
+
+        let mut count = 0;
+        let mut threads = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, &mut count, &mut threads) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get all threads: {}", r);
+        }
+
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let t = unsafe { slice::from_raw_parts(threads, count as usize) }.to_vec();
+        self.deallocate(threads as *mut c_uchar);
+
+        return t;
+    }
+
+    fn get_class_fields(&self, class: *mut jclass) -> Vec<jfieldID> {
+        let f = unsafe { (**self.internal).GetClassFields }
+            .expect("jvmtiEnv.GetClassFields not found");
+
+        let mut count = 0;
+        let mut fields = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, *class, &mut count, &mut fields) };
+        if r == jvmtiError_JVMTI_ERROR_CLASS_NOT_PREPARED {
+            return Vec::new();
+        } else if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get class fields: {}", r);
+        }
+
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let v = unsafe { slice::from_raw_parts(fields, count as usize) }.to_vec();
+        self.deallocate(fields as *mut c_uchar);
+
+        return v;
+    }
+
+    fn get_class_loader(&self, class: *mut jclass) -> Option<jobject> {
+        let f = unsafe { (**self.internal).GetClassLoader }
+            .expect("jvmtiEnv.GetClassLoader not found");
+
+        let mut loader = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, *class, &mut loader) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get class loader: {}", r);
+        }
+
+        if loader == ptr::null_mut() {
+            return None;
+        }
+
+        return Some(loader);
+    }
+
+    fn get_class_module(&self, class: *mut jclass) -> Option<jobject> {
+        // GetClassModule is not available before Java 9, where every class is in the unnamed module
+        let f = unsafe { (**self.internal).GetClassModule }?;
+
+        let mut module = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, *class, &mut module) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get class module: {}", r);
+        }
+
+        if module == ptr::null_mut() {
+            return None;
+        }
+
+        return Some(module);
+    }
+
     fn get_class_signature(&self, class: *mut jclass) -> (String, String) {
         let mut signature = ptr::null_mut();
         let mut generic = ptr::null_mut();
@@ -132,6 +255,83 @@ impl JVMTI for DefaultJVMTI {
         return (s, g);
     }
 
+    fn get_field_modifiers(&self, class: *mut jclass, field: jfieldID) -> jint {
+        let f = unsafe { (**self.internal).GetFieldModifiers }
+            .expect("jvmtiEnv.GetFieldModifiers not found");
+
+        let mut modifiers = 0;
+
+        let r = unsafe { f(self.internal, *class, field, &mut modifiers) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get field modifiers: {}", r);
+        }
+
+        return modifiers;
+    }
+
+    fn get_field_name(&self, class: *mut jclass, field: jfieldID) -> (String, String) {
+        let f = unsafe { (**self.internal).GetFieldName }
+            .expect("jvmtiEnv.GetFieldName not found");
+
+        let mut name = ptr::null_mut();
+        let mut signature = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, *class, field, &mut name, &mut signature, ptr::null_mut()) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get field name: {}", r);
+        }
+
+        return (self.to_string(name), self.to_string(signature));
+    }
+
+    fn get_implemented_interfaces(&self, class: *mut jclass) -> Vec<jclass> {
+        let f = unsafe { (**self.internal).GetImplementedInterfaces }
+            .expect("jvmtiEnv.GetImplementedInterfaces not found");
+
+        let mut count = 0;
+        let mut interfaces = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, *class, &mut count, &mut interfaces) };
+        if r == jvmtiError_JVMTI_ERROR_CLASS_NOT_PREPARED {
+            return Vec::new();
+        } else if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get implemented interfaces: {}", r);
+        }
+
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let v = unsafe { slice::from_raw_parts(interfaces, count as usize) }.to_vec();
+        self.deallocate(interfaces as *mut c_uchar);
+
+        return v;
+    }
+
+    fn get_line_number_table(&self, method: jmethodID) -> Vec<jvmtiLineNumberEntry> {
+        let f = unsafe { (**self.internal).GetLineNumberTable }
+            .expect("jvmtiEnv.GetLineNumberTable not found");
+
+        let mut count = 0;
+        let mut table = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, method, &mut count, &mut table) };
+        if r == jvmtiError_JVMTI_ERROR_ABSENT_INFORMATION || r == jvmtiError_JVMTI_ERROR_NATIVE_METHOD {
+            return Vec::new();
+        } else if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get line number table: {}", r);
+        }
+
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let t = unsafe { slice::from_raw_parts(table, count as usize) }.to_vec();
+        self.deallocate(table as *mut c_uchar);
+
+        return t;
+    }
+
     fn get_loaded_classes(&self) -> Self::LoadedClassesIterator {
         let f = unsafe { (**self.internal).GetLoadedClasses }
             .expect("jvmtiEnv.GetLoadedClasses not found");
@@ -147,6 +347,123 @@ impl JVMTI for DefaultJVMTI {
         return ArrayPointerLoadedClassesIterator { count, classes };
     }
 
+    fn get_method_declaring_class(&self, method: jmethodID) -> jclass {
+        let f = unsafe { (**self.internal).GetMethodDeclaringClass }
+            .expect("jvmtiEnv.GetMethodDeclaringClass not found");
+
+        let mut class = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, method, &mut class) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get method declaring class: {}", r);
+        }
+
+        return class;
+    }
+
+    fn get_method_name(&self, method: jmethodID) -> (String, String) {
+        let f = unsafe { (**self.internal).GetMethodName }
+            .expect("jvmtiEnv.GetMethodName not found");
+
+        let mut name = ptr::null_mut();
+        let mut signature = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, method, &mut name, &mut signature, ptr::null_mut()) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get method name: {}", r);
+        }
+
+        return (self.to_string(name), self.to_string(signature));
+    }
+
+    fn get_object_hash_code(&self, object: jobject) -> jint {
+        let f = unsafe { (**self.internal).GetObjectHashCode }
+            .expect("jvmtiEnv.GetObjectHashCode not found");
+
+        let mut hash_code = 0;
+
+        let r = unsafe { f(self.internal, object, &mut hash_code) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get object hash code: {}", r);
+        }
+
+        return hash_code;
+    }
+
+    fn get_source_file_name(&self, class: jclass) -> Option<String> {
+        let f = unsafe { (**self.internal).GetSourceFileName }
+            .expect("jvmtiEnv.GetSourceFileName not found");
+
+        let mut name = ptr::null_mut();
+
+        let r = unsafe { f(self.internal, class, &mut name) };
+        if r == jvmtiError_JVMTI_ERROR_ABSENT_INFORMATION {
+            return None;
+        } else if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get source file name: {}", r);
+        }
+
+        return Some(self.to_string(name));
+    }
+
+    fn get_stack_trace(&self, thread: jthread, max_frame_count: jint) -> Result<Vec<jvmtiFrameInfo>, jvmtiError> {
+        let f = unsafe { (**self.internal).GetStackTrace }
+            .expect("jvmtiEnv.GetStackTrace not found");
+
+        let mut count = 0;
+        let mut frames = vec![jvmtiFrameInfo::default(); max_frame_count as usize];
+
+        let r = unsafe { f(self.internal, thread, 0, max_frame_count, frames.as_mut_ptr(), &mut count) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            return Err(r);
+        }
+
+        frames.truncate(count as usize);
+        return Ok(frames);
+    }
+
+    fn get_tag(&self, object: *mut jobject) -> jlong {
+        let f = unsafe { (**self.internal).GetTag }
+            .expect("jvmtiEnv.GetTag not found");
+
+        let mut tag = 0;
+
+        let r = unsafe { f(self.internal, *object, &mut tag) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            panic!("unable to get tag: {}", r);
+        }
+
+        return tag;
+    }
+
+    fn get_thread_info(&self, thread: jthread) -> Result<ThreadInfo, jvmtiError> {
+        let f = unsafe { (**self.internal).GetThreadInfo }
+            .expect("jvmtiEnv.GetThreadInfo not found");
+
+        let mut info: jvmtiThreadInfo = Default::default();
+
+        let r = unsafe { f(self.internal, thread, &mut info) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            return Err(r);
+        }
+
+        return Ok(ThreadInfo { daemon: info.is_daemon != 0, name: self.to_string(info.name), priority: info.priority });
+    }
+
+    fn get_thread_state(&self, thread: jthread) -> Result<jint, jvmtiError> {
+        let f = unsafe { (**self.internal).GetThreadState }
+            .expect("jvmtiEnv.GetThreadState not found");
+
+        let mut state = 0;
+
+        let r = unsafe { f(self.internal, thread, &mut state) };
+        if r != jvmtiError_JVMTI_ERROR_NONE {
+            return Err(r);
+        }
+
+        return Ok(state);
+    }
+
     fn set_event_callbacks(&self, callbacks: *const jvmtiEventCallbacks) {
         let f = unsafe { (**self.internal).SetEventCallbacks }
             .expect("jvmtiEnv.SetEventCallbacks method not found");
@@ -205,3 +522,10 @@ impl Iterator for ArrayPointerLoadedClassesIterator {
         return r;
     }
 }
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThreadInfo {
+    pub daemon: bool,
+    pub name: String,
+    pub priority: jint,
+}
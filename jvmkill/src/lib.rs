@@ -14,16 +14,18 @@
  * limitations under the License.
  */
 
+use std::ffi::CStr;
 use std::os::raw::{c_char, c_void};
 use std::ptr;
 use std::sync::Mutex;
 
 use crate::action::Actions;
-use crate::bindings::{JavaVM, jint, JNIEnv, jvmtiEnv, jvmtiEvent_JVMTI_EVENT_RESOURCE_EXHAUSTED, jvmtiEventCallbacks, jvmtiEventMode_JVMTI_ENABLE};
-use crate::context::Context;
+use crate::bindings::{JavaVM, jint, JNI_ERR, JNIEnv, jvmtiEnv, jvmtiEvent_JVMTI_EVENT_RESOURCE_EXHAUSTED, jvmtiEventCallbacks, jvmtiEventMode_JVMTI_ENABLE};
+use crate::context::{Cause, Context, Outcome};
 use crate::jmx::ManagementFactory;
 use crate::jni::DefaultJNI;
 use crate::jvmti::{DefaultJVMTI, JVMTI};
+use crate::report::Report;
 
 #[cfg_attr(test, macro_use)]
 mod test_macros;
@@ -34,6 +36,10 @@ mod heap;
 mod jmx;
 mod jni;
 mod jvmti;
+mod metrics;
+mod path_template;
+mod report;
+mod sink;
 
 mod bindings {
     #![allow(dead_code, non_camel_case_types, non_snake_case, non_upper_case_globals)]
@@ -45,10 +51,20 @@ static mut CONTEXT: Option<Mutex<Context>> = None;
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn Agent_OnLoad(vm: *mut JavaVM, options: *mut c_char, _reserved: *mut c_void) -> jint {
-    Some(Mutex::new(Context::new(options)));
+    let context = match Context::new(options) {
+        Ok(c) => c,
+        Err(e) => {
+            for p in &e.problems {
+                eprintln!("jvmkill: {}", p);
+            }
+
+            eprintln!("jvmkill: invalid options, set lenient=1 to ignore them");
+            return JNI_ERR;
+        }
+    };
 
     unsafe {
-        CONTEXT = Some(Mutex::new(Context::new(options)))
+        CONTEXT = Some(Mutex::new(context))
     }
 
     let j = DefaultJVMTI::from(vm);
@@ -58,18 +74,44 @@ pub extern "C" fn Agent_OnLoad(vm: *mut JavaVM, options: *mut c_char, _reserved:
     return 0;
 }
 
-unsafe extern "C" fn resource_exhausted(jvmti_env: *mut jvmtiEnv, jni_env: *mut JNIEnv, flags: jint, _reserved: *const c_void, _description: *const c_char) {
+unsafe extern "C" fn resource_exhausted(jvmti_env: *mut jvmtiEnv, jni_env: *mut JNIEnv, flags: jint, _reserved: *const c_void, description: *const c_char) {
     match &CONTEXT {
         None => panic!("context not yet set"),
         Some(m) => {
             let mut c = m.lock().unwrap();
-            if c.record() {
-                let jvmti = DefaultJVMTI::new(jvmti_env);
-                let jni = DefaultJNI::new(jni_env);
-                let factory = ManagementFactory::new(&jni);
+            let description = to_string(description);
+            let cause = Cause::new(flags, &description);
+
+            let triggered = match c.record(cause) {
+                Outcome::Recorded if !c.parameters.event_actions.is_empty() => false,
+                Outcome::Triggered => true,
+                _ => return,
+            };
+
+            let jvmti = DefaultJVMTI::new(jvmti_env);
+            let jni = DefaultJNI::new(jni_env);
+            let factory = ManagementFactory::new(&jni);
 
-                Actions::new(&c.parameters, &jvmti, &factory).execute(flags);
+            let report = match &c.parameters.report_path {
+                Some(p) => Report::with_path(c.parameters.report_format, p),
+                None => Report::new(c.parameters.report_format),
+            };
+            report.set_event(cause, flags, &description, c.count, c.count_threshold);
+
+            if triggered {
+                Actions::new(&c.parameters, cause, &jvmti, &jni, &factory, &c.metrics, &c.heap_walked, &report).execute(flags);
+            } else {
+                Actions::for_event(&c.parameters, &jvmti, &jni, &factory, &c.metrics, &c.heap_walked, &report).execute(flags);
+                report.emit();
             }
         }
     }
 }
+
+unsafe fn to_string(s: *const c_char) -> String {
+    if s == ptr::null() {
+        return String::new();
+    }
+
+    return String::from(CStr::from_ptr(s).to_string_lossy());
+}
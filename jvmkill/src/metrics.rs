@@ -0,0 +1,288 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::context::{Cause, Parameters};
+
+const PREFIX: &str = "jvmkill";
+
+// Metric name to label set to value
+type Samples = BTreeMap<String, BTreeMap<Vec<(String, String)>, f64>>;
+
+// Sends counters and gauges to a StatsD server, using the DogStatsD extension for tags, and keeps their latest values
+// for a Prometheus textfile. Metrics are best-effort: a metric that cannot be sent or written is reported on stderr
+// and otherwise ignored.
+pub struct Metrics {
+    count_threshold: usize,
+    counters: RefCell<Samples>,
+    gauges: RefCell<Samples>,
+    last_event: Cell<Option<i64>>,
+    prometheus_textfile: Option<PathBuf>,
+    statsd_address: Option<String>,
+    time_threshold: Duration,
+}
+
+impl Metrics {
+    pub fn new(parameters: &Parameters) -> Self {
+        return Self {
+            count_threshold: parameters.count_threshold,
+            counters: RefCell::new(BTreeMap::new()),
+            gauges: RefCell::new(BTreeMap::new()),
+            last_event: Cell::new(None),
+            prometheus_textfile: parameters.prometheus_textfile.clone(),
+            statsd_address: parameters.statsd_address.clone(),
+            time_threshold: parameters.time_threshold,
+        };
+    }
+
+    pub fn count(&self, name: &str, tags: &[(&str, &str)]) {
+        *self.counters.borrow_mut().entry(String::from(name)).or_default().entry(labels(tags)).or_insert(0.0) += 1.0;
+        self.send(name, "1|c", tags);
+    }
+
+    pub fn event(&self, cause: Cause) {
+        self.last_event.set(Some(OffsetDateTime::now_utc().unix_timestamp()));
+        self.count("events", &[("cause", cause.name())]);
+        self.flush();
+    }
+
+    // Atomically rewrites the Prometheus textfile, if configured, so that a collector never reads a partial file
+    pub fn flush(&self) {
+        let path = match &self.prometheus_textfile {
+            Some(p) => p,
+            None => return,
+        };
+
+        if let Err(e) = write(path, &self.textfile()) {
+            eprintln!("Unable to write metrics to {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn gauge(&self, name: &str, value: i64, tags: &[(&str, &str)]) {
+        self.gauges.borrow_mut().entry(String::from(name)).or_default().insert(labels(tags), value as f64);
+        self.send(name, &format!("{}|g", value), tags);
+    }
+
+    fn send(&self, name: &str, value: &str, tags: &[(&str, &str)]) {
+        let address = match &self.statsd_address {
+            Some(a) => a,
+            None => return,
+        };
+
+        if let Err(e) = send(address, &format(name, value, tags)) {
+            eprintln!("Unable to send metric {} to {}: {}", name, address, e);
+        }
+    }
+
+    fn textfile(&self) -> String {
+        let mut s = String::new();
+
+        for (name, samples) in self.counters.borrow().iter() {
+            exposition(&mut s, &format!("{}_total", name), "counter", samples);
+        }
+
+        let mut gauges = self.gauges.borrow().clone();
+        gauges.entry(String::from("count.threshold")).or_default().insert(Vec::new(), self.count_threshold as f64);
+        gauges.entry(String::from("time.threshold.seconds")).or_default().insert(Vec::new(), self.time_threshold.as_secs_f64());
+
+        if let Some(t) = self.last_event.get() {
+            gauges.entry(String::from("last.event.timestamp.seconds")).or_default().insert(Vec::new(), t as f64);
+        }
+
+        for (name, samples) in &gauges {
+            exposition(&mut s, name, "gauge", samples);
+        }
+
+        return s;
+    }
+}
+
+// Writes a metric family in the Prometheus text exposition format, e.g. jvmkill_events_total{cause="heap"} 1
+fn exposition(s: &mut String, name: &str, kind: &str, samples: &BTreeMap<Vec<(String, String)>, f64>) {
+    let name = format!("{}_{}", PREFIX, name.replace('.', "_"));
+    s.push_str(&format!("# TYPE {} {}\n", name, kind));
+
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            s.push_str(&format!("{} {}\n", name, value));
+        } else {
+            let l: Vec<String> = labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, label_value(v))).collect();
+            s.push_str(&format!("{}{{{}}} {}\n", name, l.join(","), value));
+        }
+    }
+}
+
+fn format(name: &str, value: &str, tags: &[(&str, &str)]) -> String {
+    let mut s = format!("{}.{}:{}", PREFIX, name, value);
+
+    if !tags.is_empty() {
+        let t: Vec<String> = tags.iter().map(|(k, v)| format!("{}:{}", k, tag_value(v))).collect();
+        s.push_str("|#");
+        s.push_str(&t.join(","));
+    }
+
+    return s;
+}
+
+fn label_value(s: &str) -> String {
+    return s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+}
+
+fn labels(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+    return tags.iter().map(|(k, v)| (String::from(*k), String::from(*v))).collect();
+}
+
+fn send(address: &str, message: &str) -> io::Result<()> {
+    let a = address.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))?;
+
+    let socket = UdpSocket::bind(if a.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    socket.send_to(message.as_bytes(), a)?;
+    return Ok(());
+}
+
+// Memory pool names such as "G1 Eden Space" become g1_eden_space, since DogStatsD reserves some punctuation in tags
+fn tag_value(s: &str) -> String {
+    return s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+}
+
+// The textfile collector ignores files without a .prom extension, so the temporary file is never read
+fn write(path: &Path, content: &str) -> io::Result<()> {
+    if let Some(p) = path.parent() {
+        fs::create_dir_all(p)?;
+    }
+
+    let mut t = path.as_os_str().to_os_string();
+    t.push(".tmp");
+
+    let mut f = fs::File::create(&t)?;
+    f.write_all(content.as_bytes())?;
+    f.sync_all()?;
+
+    return fs::rename(&t, path);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use crate::context::{Cause, Parameters};
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn count() {
+        let (socket, metrics) = create();
+
+        metrics.count("events", &[("cause", "heap")]);
+
+        assert_eq!(receive(&socket), "jvmkill.events:1|c|#cause:heap");
+    }
+
+    #[test]
+    fn gauge() {
+        let (socket, metrics) = create();
+
+        metrics.gauge("memory.used", 42, &[("pool", "G1 Eden Space")]);
+        metrics.gauge("memory.max", -1, &[]);
+
+        assert_eq!(receive(&socket), "jvmkill.memory.used:42|g|#pool:g1_eden_space");
+        assert_eq!(receive(&socket), "jvmkill.memory.max:-1|g");
+    }
+
+    #[test]
+    fn disabled() {
+        let metrics = Metrics::new(&Parameters { ..Default::default() });
+
+        metrics.count("events", &[]);
+        metrics.flush();
+    }
+
+    #[test]
+    fn prometheus_textfile() {
+        let t = TempDir::new("jvmkill").unwrap();
+        let path = t.path().join("metrics").join("jvmkill.prom");
+        let metrics = Metrics::new(&Parameters { count_threshold: 2, prometheus_textfile: Some(path.clone()), time_threshold: Duration::from_millis(500), ..Default::default() });
+
+        metrics.event(Cause::Heap);
+        metrics.event(Cause::Heap);
+        metrics.event(Cause::Threads);
+        metrics.gauge("memory.used", 42, &[("pool", "G1 \"Eden\" Space")]);
+        metrics.gauge("memory.used", 43, &[("pool", "G1 \"Eden\" Space")]);
+        metrics.flush();
+
+        let s = fs::read_to_string(&path).unwrap();
+        let (timestamp, lines): (Vec<&str>, Vec<&str>) = s.lines().partition(|l| l.starts_with("jvmkill_last_event_timestamp_seconds "));
+
+        assert_eq!(lines, vec![
+            "# TYPE jvmkill_events_total counter",
+            "jvmkill_events_total{cause=\"heap\"} 2",
+            "jvmkill_events_total{cause=\"threads\"} 1",
+            "# TYPE jvmkill_count_threshold gauge",
+            "jvmkill_count_threshold 2",
+            "# TYPE jvmkill_last_event_timestamp_seconds gauge",
+            "# TYPE jvmkill_memory_used gauge",
+            "jvmkill_memory_used{pool=\"G1 \\\"Eden\\\" Space\"} 43",
+            "# TYPE jvmkill_time_threshold_seconds gauge",
+            "jvmkill_time_threshold_seconds 0.5",
+        ]);
+        assert_eq!(timestamp.len(), 1);
+        assert!(!path.with_extension("prom.tmp").exists());
+    }
+
+    #[test]
+    fn prometheus_textfile_unwritable() {
+        let t = TempDir::new("jvmkill").unwrap();
+        fs::write(t.path().join("metrics"), "").unwrap();
+        let path = t.path().join("metrics").join("jvmkill.prom");
+        let metrics = Metrics::new(&Parameters { prometheus_textfile: Some(path.clone()), ..Default::default() });
+
+        metrics.event(Cause::Heap);
+        metrics.flush();
+
+        assert!(!path.exists());
+    }
+
+    fn create() -> (UdpSocket, Metrics) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        let metrics = Metrics::new(&Parameters { statsd_address: Some(socket.local_addr().unwrap().to_string()), ..Default::default() });
+        return (socket, metrics);
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut b = [0; 1024];
+        let n = socket.recv(&mut b).unwrap();
+        return String::from_utf8_lossy(&b[..n]).into_owned();
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{env, fs, io};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use time::OffsetDateTime;
+
+pub struct PathTemplate {
+    template: PathBuf,
+}
+
+impl PathTemplate {
+    pub fn new(path: &PathBuf) -> Self {
+        let mut p = env::current_dir()
+            .expect("current directory not found");
+
+        p.push(path);
+
+        return Self { template: p };
+    }
+
+    pub fn create_parent(path: &Path) -> io::Result<()> {
+        return match path.parent() {
+            Some(p) => fs::create_dir_all(p),
+            None => Ok(()),
+        };
+    }
+
+    // Whether a file in the same directory could have been produced by resolving this template, optionally with a
+    // compression extension appended. Each format code matches any non-empty run of characters.
+    pub fn matches(&self, path: &Path) -> bool {
+        let name = self.template.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut chars = name.chars().peekable();
+        let mut pattern = String::from("^");
+
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('%', Some('%')) => {
+                    chars.next();
+                    pattern.push('%');
+                }
+                ('%', Some(_)) => {
+                    chars.next();
+                    pattern.push_str(".+?");
+                }
+                (c, _) => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        pattern.push_str(r"(\.gz|\.zst)?$");
+
+        return path.file_name()
+            .map(|n| Regex::new(&pattern).expect("invalid path template").is_match(&n.to_string_lossy()))
+            .unwrap_or(false);
+    }
+
+    pub fn resolve(&self) -> PathBuf {
+        return PathBuf::from(OffsetDateTime::now_utc().format(self.template.to_str().unwrap()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use crate::path_template::PathTemplate;
+
+    #[test]
+    fn create_parent() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("alpha").join("bravo").join("charlie");
+
+        PathTemplate::create_parent(&p).unwrap();
+
+        assert!(t.path().join("alpha").join("bravo").is_dir());
+        assert!(!p.exists());
+    }
+
+    #[test]
+    fn matches() {
+        let t = PathTemplate::new(&PathBuf::from("/alpha/bravo-%Y-%m-%d.hprof"));
+
+        assert!(t.matches(&PathBuf::from("/alpha/bravo-2020-01-02.hprof")));
+        assert!(t.matches(&PathBuf::from("/alpha/bravo-2020-01-02.hprof.gz")));
+        assert!(t.matches(&PathBuf::from("/alpha/bravo-2020-01-02.hprof.zst")));
+        assert!(!t.matches(&PathBuf::from("/alpha/bravo-2020-01-02.hprof.bak")));
+        assert!(!t.matches(&PathBuf::from("/alpha/charlie-2020-01-02.hprof")));
+        assert!(!t.matches(&PathBuf::from("/alpha/bravo.hprof")));
+    }
+
+    #[test]
+    fn matches_literal() {
+        let t = PathTemplate::new(&PathBuf::from("/alpha/100%%.hprof"));
+
+        assert!(t.matches(&PathBuf::from("/alpha/100%.hprof")));
+        assert!(!t.matches(&PathBuf::from("/alpha/1000.hprof")));
+    }
+
+    #[test]
+    fn resolve_absolute() {
+        assert_eq!(PathTemplate::new(&PathBuf::from("/alpha/bravo")).resolve(), PathBuf::from("/alpha/bravo"));
+    }
+
+    #[test]
+    fn resolve_relative() {
+        assert_eq!(PathTemplate::new(&PathBuf::from("alpha")).resolve(), env::current_dir().unwrap().join("alpha"));
+    }
+
+    #[test]
+    fn resolve_template() {
+        let p = PathTemplate::new(&PathBuf::from("/alpha-%Y")).resolve();
+
+        assert_ne!(p, PathBuf::from("/alpha-%Y"));
+        assert!(p.to_str().unwrap().starts_with("/alpha-2"));
+    }
+}
@@ -0,0 +1,243 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde_json::{json, Map, Value};
+use time::OffsetDateTime;
+
+use crate::bindings::{jint, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR, JVMTI_RESOURCE_EXHAUSTED_THREADS};
+use crate::context::Cause;
+use crate::path_template::PathTemplate;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Text,
+}
+
+pub struct Report {
+    document: RefCell<Map<String, Value>>,
+    format: Format,
+    output: RefCell<Output>,
+}
+
+enum Output {
+    File(File),
+    Stdout,
+}
+
+impl Report {
+    pub fn new(format: Format) -> Self {
+        return Self { document: RefCell::new(Map::new()), format, output: RefCell::new(Output::Stdout) };
+    }
+
+    // Appends so that the reports of earlier events are kept. Falls back to stdout if the report file cannot be opened,
+    // since a report is better than none.
+    pub fn with_path(format: Format, path: &PathBuf) -> Self {
+        let p = PathTemplate::new(path).resolve();
+
+        let output = match PathTemplate::create_parent(&p).and_then(|_| OpenOptions::new().create(true).append(true).open(&p)) {
+            Ok(f) => Output::File(f),
+            Err(e) => {
+                eprintln!("Unable to open report file {}, writing to stdout: {}", p.display(), e);
+                Output::Stdout
+            }
+        };
+
+        return Self { document: RefCell::new(Map::new()), format, output: RefCell::new(output) };
+    }
+
+    pub fn emit(&self) {
+        if self.format == Format::Json {
+            self.write(&self.document().to_string());
+        }
+
+        let result = match &mut *self.output.borrow_mut() {
+            Output::File(f) => f.sync_all(),
+            Output::Stdout => io::stdout().flush(),
+        };
+
+        if let Err(e) = result {
+            eprintln!("Unable to flush report: {}", e);
+        }
+    }
+
+    pub fn document(&self) -> Value {
+        return Value::Object(self.document.borrow().clone());
+    }
+
+    pub fn println(&self, s: &str) {
+        if self.format == Format::Text {
+            self.write(s);
+        }
+    }
+
+    pub fn set(&self, key: &str, value: Value) {
+        self.document.borrow_mut().insert(String::from(key), value);
+    }
+
+    pub fn set_event(&self, cause: Cause, flags: jint, description: &str, count: usize, count_threshold: usize) {
+        let mut causes = Vec::new();
+
+        for (flag, name) in &[
+            (JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR, "OOM_ERROR"),
+            (JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, "JAVA_HEAP"),
+            (JVMTI_RESOURCE_EXHAUSTED_THREADS, "THREADS"),
+        ] {
+            if flags & *flag as jint == *flag as jint {
+                causes.push(*name);
+            }
+        }
+
+        self.set("event", json!({
+            "cause": cause.name(),
+            "causes": causes,
+            "count": count,
+            "countThreshold": count_threshold,
+            "description": description,
+            "flags": flags,
+            "pid": std::process::id(),
+            "time": OffsetDateTime::now_utc().format("%Y-%m-%dT%H:%M:%S%z"),
+        }));
+    }
+
+    pub fn skip(&self, key: &str, reason: &str) {
+        self.set(key, json!({ "skipped": reason }));
+
+        if self.format == Format::Text {
+            match &mut *self.output.borrow_mut() {
+                Output::File(f) => Self::write_file(f, reason),
+                Output::Stdout => eprintln!("{}", reason),
+            }
+        }
+    }
+
+    fn write(&self, s: &str) {
+        match &mut *self.output.borrow_mut() {
+            Output::File(f) => Self::write_file(f, s),
+            Output::Stdout => println!("{}", s),
+        }
+    }
+
+    // A failed write is echoed to stderr so that the content is not lost
+    fn write_file(f: &mut File, s: &str) {
+        if let Err(e) = writeln!(f, "{}", s) {
+            eprintln!("Unable to write report file: {}\n{}", e, s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use crate::bindings::{jint, JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP, JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR};
+    use crate::context::Cause;
+    use crate::report::{Format, Report};
+
+    #[test]
+    fn emit_json_with_path() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("alpha").join("report.json");
+
+        let r = Report::with_path(Format::Json, &p);
+        r.println("bravo");
+        r.set("charlie", json!("delta"));
+        r.emit();
+
+        assert_eq!(fs::read_to_string(&p).unwrap(), "{\"charlie\":\"delta\"}\n");
+    }
+
+    #[test]
+    fn emit_json_with_path_appends() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("report.json");
+
+        for v in &["alpha", "bravo"] {
+            let r = Report::with_path(Format::Json, &p);
+            r.set("charlie", json!(v));
+            r.emit();
+        }
+
+        assert_eq!(fs::read_to_string(&p).unwrap(), "{\"charlie\":\"alpha\"}\n{\"charlie\":\"bravo\"}\n");
+    }
+
+    #[test]
+    fn emit_text_with_path() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        let p = t.path().join("alpha").join("report.txt");
+
+        let r = Report::with_path(Format::Text, &p);
+        r.println("bravo");
+        r.skip("charlie", "delta");
+        r.set("echo", json!("foxtrot"));
+        r.emit();
+
+        assert_eq!(fs::read_to_string(&p).unwrap(), "bravo\ndelta\n");
+    }
+
+    #[test]
+    fn emit_with_unwritable_path() {
+        let t = tempdir::TempDir::new("jvmkill").unwrap();
+        fs::write(t.path().join("alpha"), "").unwrap();
+        let p = t.path().join("alpha").join("report.json");
+
+        let r = Report::with_path(Format::Json, &p);
+        r.set("bravo", json!("charlie"));
+        r.emit();
+
+        assert!(!p.exists());
+    }
+
+    #[test]
+    fn set() {
+        let r = Report::new(Format::Json);
+        r.set("alpha", json!(1));
+        r.set("bravo", json!({ "charlie": "delta" }));
+
+        assert_eq!(r.document(), json!({ "alpha": 1, "bravo": { "charlie": "delta" } }));
+    }
+
+    #[test]
+    fn set_event() {
+        let r = Report::new(Format::Json);
+        r.set_event(Cause::Heap, (JVMTI_RESOURCE_EXHAUSTED_OOM_ERROR | JVMTI_RESOURCE_EXHAUSTED_JAVA_HEAP) as jint, "Java heap space", 2, 1);
+
+        let e = &r.document()["event"];
+        assert_eq!(e["cause"], json!("heap"));
+        assert_eq!(e["causes"], json!(["OOM_ERROR", "JAVA_HEAP"]));
+        assert_eq!(e["count"], json!(2));
+        assert_eq!(e["countThreshold"], json!(1));
+        assert_eq!(e["description"], json!("Java heap space"));
+        assert_eq!(e["flags"], json!(3));
+        assert_eq!(e["pid"], json!(std::process::id()));
+    }
+
+    #[test]
+    fn skip() {
+        let r = Report::new(Format::Json);
+        r.skip("alpha", "test-reason");
+
+        assert_eq!(r.document(), json!({ "alpha": { "skipped": "test-reason" } }));
+    }
+}
@@ -0,0 +1,277 @@
+/*
+ * Copyright 2015-2020 the original author or authors.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use time::{Format, OffsetDateTime};
+
+use crate::context::{LogSink, Parameters};
+
+const IDENTIFIER: &str = "jvmkill";
+const JOURNALD_PATH: &str = "/run/systemd/journal/socket";
+const SYSLOG_PATH: &str = "/dev/log";
+
+// RFC 5424 requires a private enterprise number in custom structured data ids; 32473 is reserved for documentation
+const SD_ID: &str = "jvmkill@32473";
+
+// Facility user-level messages
+const FACILITY: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+// Sends the agent's event lines to syslog or the systemd journal, with the event details attached as structured
+// fields. Like metrics, logging is best-effort: a line that cannot be sent is reported on stderr and otherwise ignored.
+pub struct Sink {
+    kind: Option<LogSink>,
+    path: PathBuf,
+    summary: bool,
+}
+
+impl Sink {
+    pub fn new(parameters: &Parameters) -> Self {
+        let path = match parameters.log_sink {
+            Some(LogSink::Journald) => JOURNALD_PATH,
+            _ => SYSLOG_PATH,
+        };
+
+        return Self { kind: parameters.log_sink, path: PathBuf::from(path), summary: parameters.log_summary };
+    }
+
+    pub fn log(&self, severity: Severity, message: &str, fields: &[(&str, String)]) {
+        let m = match self.kind {
+            Some(LogSink::Journald) => journald(severity, message, fields),
+            Some(LogSink::Syslog) => syslog(severity, message, fields),
+            None => return,
+        };
+
+        if let Err(e) = send(&self.path, &m) {
+            eprintln!("Unable to log to {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn summary(&self, document: &Value) {
+        if self.summary {
+            self.log(Severity::Info, &document.to_string(), &fields(&document["event"]));
+        }
+    }
+}
+
+// Extracts the structured fields attached to every line from the event in a report document
+pub fn fields(event: &Value) -> Vec<(&'static str, String)> {
+    let mut f = Vec::new();
+
+    for key in &["cause", "count", "countThreshold", "pid"] {
+        match &event[*key] {
+            Value::Null => {}
+            Value::String(s) => f.push((*key, s.clone())),
+            v => f.push((*key, v.to_string())),
+        }
+    }
+
+    return f;
+}
+
+fn hostname() -> String {
+    let mut b = [0u8; 256];
+
+    if unsafe { libc::gethostname(b.as_mut_ptr() as *mut libc::c_char, b.len()) } != 0 {
+        return String::from("-");
+    }
+
+    let n = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    return String::from_utf8_lossy(&b[..n]).into_owned();
+}
+
+// Uses the journal's native protocol, where a value containing a newline is sent as a little-endian length and the raw
+// bytes instead of KEY=VALUE
+fn journald(severity: Severity, message: &str, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut b = Vec::new();
+
+    journald_field(&mut b, "MESSAGE", message);
+    journald_field(&mut b, "PRIORITY", &(severity as u8).to_string());
+    journald_field(&mut b, "SYSLOG_IDENTIFIER", IDENTIFIER);
+
+    for (k, v) in fields {
+        journald_field(&mut b, &format!("JVMKILL_{}", upper_snake(k)), v);
+    }
+
+    return b;
+}
+
+fn journald_field(b: &mut Vec<u8>, key: &str, value: &str) {
+    b.extend_from_slice(key.as_bytes());
+
+    if value.contains('\n') {
+        b.push(b'\n');
+        b.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        b.push(b'=');
+    }
+
+    b.extend_from_slice(value.as_bytes());
+    b.push(b'\n');
+}
+
+fn send(path: &PathBuf, message: &[u8]) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(message, path)?;
+    return Ok(());
+}
+
+fn syslog(severity: Severity, message: &str, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut sd = String::from("-");
+
+    if !fields.is_empty() {
+        let p: Vec<String> = fields.iter().map(|(k, v)| format!("{}=\"{}\"", k, syslog_escape(v))).collect();
+        sd = format!("[{} {}]", SD_ID, p.join(" "));
+    }
+
+    return format!("<{}>1 {} {} {} {} - {} {}",
+                   FACILITY * 8 + severity as u8,
+                   OffsetDateTime::now_utc().format(Format::Rfc3339),
+                   hostname(),
+                   IDENTIFIER,
+                   std::process::id(),
+                   sd,
+                   message).into_bytes();
+}
+
+// RFC 5424 requires '"', '\' and ']' to be escaped in structured data parameter values
+fn syslog_escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if c == '"' || c == '\\' || c == ']' {
+            r.push('\\');
+        }
+
+        r.push(c);
+    }
+
+    return r;
+}
+
+fn upper_snake(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 4);
+
+    for c in s.chars() {
+        if c.is_ascii_uppercase() {
+            r.push('_');
+        }
+
+        r.push(c.to_ascii_uppercase());
+    }
+
+    return r;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    use crate::context::{LogSink, Parameters};
+    use crate::sink::{fields, Severity, Sink};
+
+    #[test]
+    fn journald() {
+        let (_t, socket, sink) = create(LogSink::Journald, false);
+
+        sink.log(Severity::Error, "Resource Exhausted! (2/1)", &[("cause", String::from("heap")), ("countThreshold", String::from("1"))]);
+        assert_eq!(receive(&socket), b"MESSAGE=Resource Exhausted! (2/1)\nPRIORITY=3\nSYSLOG_IDENTIFIER=jvmkill\nJVMKILL_CAUSE=heap\nJVMKILL_COUNT_THRESHOLD=1\n".to_vec());
+
+        sink.log(Severity::Info, "a\nb", &[]);
+        let mut expected = b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n".to_vec();
+        expected.extend_from_slice(b"PRIORITY=6\nSYSLOG_IDENTIFIER=jvmkill\n");
+        assert_eq!(receive(&socket), expected);
+    }
+
+    #[test]
+    fn syslog() {
+        let (_t, socket, sink) = create(LogSink::Syslog, false);
+
+        sink.log(Severity::Warning, "Resource Exhausted! (heap ignored)", &[("cause", String::from("heap")), ("description", String::from("a \"b\" [c]"))]);
+
+        let m = String::from_utf8(receive(&socket)).unwrap();
+        assert!(m.starts_with("<12>1 "), "{}", m);
+        assert!(m.ends_with(&format!(" jvmkill {} - [jvmkill@32473 cause=\"heap\" description=\"a \\\"b\\\" [c\\]\"] Resource Exhausted! (heap ignored)", std::process::id())), "{}", m);
+    }
+
+    #[test]
+    fn summary() {
+        let (_t, socket, sink) = create(LogSink::Journald, true);
+
+        sink.summary(&json!({ "event": { "cause": "heap", "count": 2 } }));
+
+        let m = String::from_utf8(receive(&socket)).unwrap();
+        assert!(m.starts_with("MESSAGE={\"event\":{\"cause\":\"heap\",\"count\":2}}\nPRIORITY=6\n"), "{}", m);
+        assert!(m.ends_with("JVMKILL_CAUSE=heap\nJVMKILL_COUNT=2\n"), "{}", m);
+    }
+
+    #[test]
+    fn summary_disabled() {
+        let (_t, socket, sink) = create(LogSink::Journald, false);
+        socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+
+        sink.summary(&json!({ "event": { "cause": "heap" } }));
+
+        let mut b = [0; 16];
+        assert!(socket.recv(&mut b).is_err());
+    }
+
+    #[test]
+    fn disabled() {
+        Sink::new(&Parameters { ..Default::default() }).log(Severity::Error, "test-message", &[]);
+    }
+
+    #[test]
+    fn event_fields() {
+        assert_eq!(fields(&json!({ "cause": "heap", "count": 2, "countThreshold": 1, "description": "Java heap space", "pid": 42 })), vec![
+            ("cause", String::from("heap")),
+            ("count", String::from("2")),
+            ("countThreshold", String::from("1")),
+            ("pid", String::from("42")),
+        ]);
+    }
+
+    fn create(kind: LogSink, summary: bool) -> (TempDir, UnixDatagram, Sink) {
+        let t = TempDir::new("jvmkill").unwrap();
+        let path = t.path().join("log.sock");
+
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        return (t, socket, Sink { kind: Some(kind), path, summary });
+    }
+
+    fn receive(socket: &UnixDatagram) -> Vec<u8> {
+        let mut b = [0; 4096];
+        let n = socket.recv(&mut b).unwrap();
+        return b[..n].to_vec();
+    }
+}